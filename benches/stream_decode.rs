@@ -0,0 +1,95 @@
+//! Decoding throughput of `process_stream` on a multi-megabyte response, fed in network-sized
+//! and in large chunks.
+//!
+//! The body is a response body recorded from a server when `STREAM_DECODE_FIXTURE` names one,
+//! for example captured with
+//!
+//! ```text
+//! curl -sN http://127.0.0.1:8080/v1/chat/completions -H 'Content-Type: application/json' \
+//!     -d @request.json > reply.sse
+//! ```
+//!
+//! Otherwise it is synthesized to mirror what an LFM2.5-Audio server sends for an interleaved
+//! reply: short text deltas between 80 ms base64 audio chunks, as SSE.
+//!
+//! Run with `cargo bench --bench stream_decode`.
+
+use base64::Engine;
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use liquid_audio_chat::api::process_stream;
+
+/// Seconds of 24 kHz audio in the synthetic reply (about 4 MB of SSE).
+const REPLY_SECS: usize = 30;
+const AUDIO_CHUNK_SAMPLES: usize = 1920;
+
+/// The recorded fixture, if `STREAM_DECODE_FIXTURE` is set.
+fn recorded_reply() -> Option<Vec<u8>> {
+    let path = std::env::var_os("STREAM_DECODE_FIXTURE")?;
+    let body = std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", path.to_string_lossy(), e));
+    Some(body)
+}
+
+fn synthetic_reply() -> Vec<u8> {
+    let b64 = base64::engine::general_purpose::STANDARD;
+    let words = ["Sure", ",", " here", " is", " the", " answer", " you", " asked", " for", "."];
+    let mut body = Vec::new();
+    let chunks = REPLY_SECS * 24_000 / AUDIO_CHUNK_SAMPLES;
+    for i in 0..chunks {
+        let text = serde_json::json!({"choices": [{"delta": {"content": words[i % words.len()]}}]});
+        body.extend_from_slice(format!("data: {}\n\n", text).as_bytes());
+        let pcm: Vec<u8> = (0..AUDIO_CHUNK_SAMPLES)
+            .flat_map(|n| ((n as f32 * 0.05).sin() * 0.3).to_le_bytes())
+            .collect();
+        let audio = serde_json::json!({
+            "choices": [{"delta": {"audio_chunk": {"data": b64.encode(&pcm)}}}]
+        });
+        body.extend_from_slice(format!("data: {}\n\n", audio).as_bytes());
+    }
+    body.extend_from_slice(b"data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n");
+    body.extend_from_slice(b"data: [DONE]\n\n");
+    body
+}
+
+fn response(body: &[u8], chunk_size: usize) -> reqwest::Response {
+    let chunks: Vec<Result<Bytes, std::io::Error>> = body
+        .chunks(chunk_size)
+        .map(|c| Ok(Bytes::copy_from_slice(c)))
+        .collect();
+    let body = reqwest::Body::wrap_stream(futures_util::stream::iter(chunks));
+    reqwest::Response::from(http::Response::new(body))
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let body = recorded_reply().unwrap_or_else(synthetic_reply);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("process_stream");
+    group.throughput(Throughput::Bytes(body.len() as u64));
+    group.sample_size(20);
+    for chunk_size in [1460, 16 * 1024, 1024 * 1024] {
+        group.bench_with_input(BenchmarkId::from_parameter(chunk_size), &chunk_size, |b, &size| {
+            b.iter_batched(
+                || response(&body, size),
+                |res| {
+                    runtime.block_on(async {
+                        let mut samples = 0;
+                        let (_, stats) = process_stream(res, |_| {}, |s| samples += s.len())
+                            .await
+                            .unwrap();
+                        assert!(stats.completed);
+                        samples
+                    })
+                },
+                criterion::BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
@@ -1,12 +1,69 @@
 //! OpenAI-compatible streaming chat client for LFM2.5-Audio.
 
+use crate::error::{Error, Result};
+use crate::sse::SseDecoder;
 use base64::Engine;
 use futures_util::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::time::Instant;
+use std::borrow::Cow;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-const B64: base64::engine::general_purpose::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+/// Passed to `on_text` for every audio chunk so text output shows where audio arrived.
+pub const AUDIO_MARKER: &str = "♪";
+
+pub(crate) const B64: base64::engine::general_purpose::GeneralPurpose =
+    base64::engine::general_purpose::STANDARD;
+
+/// Conversation mode. ASR and TTS are single-shot; interleaved keeps chat context.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Mode {
+    Asr,
+    Tts,
+    Interleaved,
+}
+
+impl Mode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Asr => "asr",
+            Mode::Tts => "tts",
+            Mode::Interleaved => "interleaved",
+        }
+    }
+
+    /// Every request is independent (context reset on the server).
+    #[inline]
+    pub fn is_single_shot(self) -> bool {
+        matches!(self, Mode::Asr | Mode::Tts)
+    }
+
+    pub fn system_prompt(self) -> &'static str {
+        match self {
+            Mode::Asr => "Perform ASR.",
+            Mode::Tts => "Perform TTS. Use the UK female voice.",
+            Mode::Interleaved => "Respond with interleaved text and audio.",
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        <Mode as clap::ValueEnum>::from_str(s, true)
+            .map_err(|_| format!("invalid mode '{}' (expected asr, tts or interleaved)", s))
+    }
+}
 
 #[derive(Clone, Serialize)]
 pub struct ChatMessage {
@@ -14,6 +71,16 @@ pub struct ChatMessage {
     pub content: serde_json::Value,
 }
 
+impl ChatMessage {
+    /// Plain-text message (`role` is "system", "user" or "assistant").
+    pub fn text(role: &str, text: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: MessageContent::Text(text.into()).into(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum MessageContent {
     Text(String),
@@ -30,103 +97,299 @@ impl From<MessageContent> for serde_json::Value {
 }
 
 #[derive(Serialize)]
-struct ChatRequestBody {
+struct ChatRequestBody<'a> {
     model: String,
-    messages: Vec<ChatMessage>,
+    messages: &'a [ChatMessage],
     stream: bool,
     max_tokens: u32,
     #[serde(rename = "reset_context", skip_serializing_if = "Option::is_none")]
     reset_context: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    /// Ask for a final chunk with token usage (empty `choices`) before `[DONE]`.
+    include_usage: bool,
 }
 
+/// One streamed completion chunk. Strings borrow from the event payload unless they contain
+/// JSON escapes.
 #[derive(Deserialize)]
-pub struct StreamChunk {
-    pub choices: Option<Vec<StreamChoice>>,
+pub struct StreamChunk<'a> {
+    #[serde(borrow)]
+    pub choices: Option<Vec<StreamChoice<'a>>>,
+    /// Set instead of `choices` when the server fails mid-stream.
+    #[serde(borrow)]
+    pub error: Option<StreamError<'a>>,
+    /// Sent in the last chunk when `stream_options.include_usage` was requested.
+    pub usage: Option<StreamUsage>,
 }
 
 #[derive(Deserialize)]
-pub struct StreamChoice {
-    pub delta: Option<StreamDelta>,
-    #[serde(rename = "finish_reason")]
-    pub finish_reason: Option<String>,
+pub struct StreamUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    pub prompt_tokens_details: Option<TokenDetails>,
+    pub completion_tokens_details: Option<TokenDetails>,
 }
 
 #[derive(Deserialize)]
-pub struct StreamDelta {
-    pub content: Option<String>,
-    #[serde(rename = "audio_chunk")]
-    pub audio_chunk: Option<AudioChunk>,
+pub struct TokenDetails {
+    pub audio_tokens: Option<u64>,
+}
+
+/// Token counts reported by the server for one response, or summed over a session.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Audio share of `prompt_tokens`, when the server breaks it down.
+    pub prompt_audio_tokens: Option<u64>,
+    /// Audio share of `completion_tokens`, when the server breaks it down.
+    pub completion_audio_tokens: Option<u64>,
 }
 
+impl TokenUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl From<&StreamUsage> for TokenUsage {
+    fn from(usage: &StreamUsage) -> Self {
+        let audio = |details: &Option<TokenDetails>| details.as_ref().and_then(|d| d.audio_tokens);
+        Self {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            prompt_audio_tokens: audio(&usage.prompt_tokens_details),
+            completion_audio_tokens: audio(&usage.completion_tokens_details),
+        }
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        let add = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+        };
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.prompt_audio_tokens = add(self.prompt_audio_tokens, other.prompt_audio_tokens);
+        self.completion_audio_tokens =
+            add(self.completion_audio_tokens, other.completion_audio_tokens);
+    }
+}
+
+/// OpenAI-style `{"error": {"message": ..., "type": ..., "code": ...}}` payload.
 #[derive(Deserialize)]
-pub struct AudioChunk {
-    pub data: String,
+pub struct StreamError<'a> {
+    #[serde(borrow)]
+    pub message: Option<Cow<'a, str>>,
+    #[serde(rename = "type", borrow)]
+    pub kind: Option<Cow<'a, str>>,
+    /// String or number depending on the server.
+    pub code: Option<serde_json::Value>,
 }
 
+impl StreamError<'_> {
+    /// `raw` (the event data) stands in for a missing message.
+    fn into_error(self, raw: &str) -> Error {
+        let code = match self.code {
+            Some(serde_json::Value::String(code)) => Some(code),
+            Some(serde_json::Value::Null) | None => self.kind.map(Cow::into_owned),
+            Some(code) => Some(code.to_string()),
+        };
+        Error::Server {
+            message: self.message.map_or_else(|| raw.to_string(), Cow::into_owned),
+            code,
+        }
+    }
+}
+
+/// Error for an `event: error`: an error object, either wrapped in `error` or bare, else the
+/// raw data as the message.
+fn error_event_to_error(data: &str) -> Error {
+    #[derive(Deserialize)]
+    struct Wrapped<'a> {
+        #[serde(borrow)]
+        error: StreamError<'a>,
+    }
+    if let Ok(wrapped) = serde_json::from_str::<Wrapped>(data) {
+        return wrapped.error.into_error(data);
+    }
+    match serde_json::from_str::<StreamError>(data) {
+        Ok(error) => error.into_error(data),
+        Err(_) if data.is_empty() => Error::Server {
+            message: "unknown error".to_string(),
+            code: None,
+        },
+        Err(_) => Error::Server {
+            message: data.to_string(),
+            code: None,
+        },
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StreamChoice<'a> {
+    #[serde(borrow)]
+    pub delta: Option<StreamDelta<'a>>,
+    #[serde(rename = "finish_reason", borrow)]
+    pub finish_reason: Option<Cow<'a, str>>,
+}
+
+#[derive(Deserialize)]
+pub struct StreamDelta<'a> {
+    #[serde(borrow)]
+    pub content: Option<Cow<'a, str>>,
+    #[serde(rename = "audio_chunk", borrow)]
+    pub audio_chunk: Option<AudioChunk<'a>>,
+}
+
+impl StreamDelta<'_> {
+    fn is_empty(&self) -> bool {
+        self.content.as_deref().is_none_or(str::is_empty) && self.audio_chunk.is_none()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AudioChunk<'a> {
+    #[serde(borrow)]
+    pub data: Cow<'a, str>,
+}
+
+/// Why the server ended the response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// Natural end of the reply.
+    Stop,
+    /// Hit `max_tokens`; the reply is truncated.
+    Length,
+    /// Withheld by the server's content filter.
+    ContentFilter,
+    ToolCalls,
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl FinishReason {
+    /// The wire name, e.g. `"stop"` or `"length"`.
+    pub fn as_str(&self) -> &str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::ContentFilter => "content_filter",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::Other(reason) => reason,
+        }
+    }
+}
+
+impl From<&str> for FinishReason {
+    fn from(reason: &str) -> Self {
+        match reason {
+            "stop" => FinishReason::Stop,
+            "length" => FinishReason::Length,
+            "content_filter" => FinishReason::ContentFilter,
+            "tool_calls" => FinishReason::ToolCalls,
+            other => FinishReason::Other(other.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct StreamStats {
     pub ttft_secs: Option<f64>,
     pub total_secs: f64,
+    /// When the finish reason arrived, in seconds of generation time (wall time minus
+    /// `backpressure_secs`). `None` if it never did.
+    pub finish_secs: Option<f64>,
+    /// Time spent waiting for the audio sink to take more (`AudioSink::ready`). The text and
+    /// audio spans exclude it, so their rates reflect the server rather than playback.
+    pub backpressure_secs: f64,
     pub text_chunk_count: usize,
     pub text_duration_secs: f64,
     pub total_audio_samples: usize,
     pub audio_duration_secs: f64,
+    /// The server ended the response (any `finish_reason`).
     pub completed: bool,
+    /// `None` if the stream ended or was cancelled without one.
+    pub finish_reason: Option<FinishReason>,
+    /// Stopped by the caller (`process_stream_until`) before the server finished.
+    pub cancelled: bool,
+    /// Events whose payload was not valid JSON and was skipped.
+    pub unparsed_events: usize,
+    /// Token counts from the server's usage chunk, if it sent one.
+    pub usage: Option<TokenUsage>,
 }
 
-/// Single-shot ASR or TTS request (resets context).
+impl StreamStats {
+    /// Completion tokens per second from the first token to the finish reason, excluding sink
+    /// backpressure, when the server reported usage.
+    pub fn tokens_per_sec(&self) -> Option<f64> {
+        let usage = self.usage?;
+        let end = self.finish_secs.unwrap_or(self.total_secs - self.backpressure_secs);
+        let secs = end - self.ttft_secs?;
+        (usage.completion_tokens > 1 && secs > 0.0).then(|| usage.completion_tokens as f64 / secs)
+    }
+}
+
+/// Single-shot ASR or TTS request (resets context). With `include_usage` the stream ends with
+/// a token usage chunk.
 pub async fn stream_single_shot(
     client: &Client,
     base_url: &str,
-    mode: &str,
+    mode: Mode,
     text: Option<&str>,
     wav_data: Option<&[u8]>,
     max_tokens: u32,
-) -> Result<reqwest::Response, String> {
-    let system = match mode {
-        "asr" => "Perform ASR.",
-        "tts" => "Perform TTS. Use the UK female voice.",
-        _ => return Err("invalid mode".into()),
-    };
-    let mut messages = vec![ChatMessage {
-        role: "system".into(),
-        content: MessageContent::Text(system.into()).into(),
-    }];
-    if mode == "asr" {
-        if let Some(wav) = wav_data {
-            messages.push(create_audio_message(wav));
+    include_usage: bool,
+) -> Result<reqwest::Response> {
+    let user = match mode {
+        Mode::Asr => wav_data.map(create_audio_message),
+        Mode::Tts => text.map(|t| ChatMessage::text("user", t)),
+        Mode::Interleaved => {
+            return Err(Error::InvalidRequest(format!(
+                "{} mode is not single-shot; use stream_chat",
+                mode
+            )))
         }
-    } else if mode == "tts" {
-        if let Some(t) = text {
-            messages.push(ChatMessage {
-                role: "user".into(),
-                content: MessageContent::Text(t.to_string()).into(),
-            });
-        }
-    }
+    };
+    let mut messages = vec![ChatMessage::text("system", mode.system_prompt())];
+    messages.extend(user);
     let body = ChatRequestBody {
         model: String::new(),
-        messages,
+        messages: &messages,
         stream: true,
         max_tokens,
         reset_context: Some(true),
+        stream_options: include_usage.then_some(StreamOptions { include_usage }),
     };
     post_stream(client, base_url, &body).await
 }
 
-/// Chat request for interleaved mode.
+/// Chat request for interleaved mode (`include_usage` as for `stream_single_shot`).
 pub async fn stream_chat(
     client: &Client,
     base_url: &str,
-    messages: Vec<ChatMessage>,
+    messages: &[ChatMessage],
     max_tokens: u32,
     reset_context: bool,
-) -> Result<reqwest::Response, String> {
+    include_usage: bool,
+) -> Result<reqwest::Response> {
     let body = ChatRequestBody {
         model: String::new(),
         messages,
         stream: true,
         max_tokens,
         reset_context: Some(reset_context),
+        stream_options: include_usage.then_some(StreamOptions { include_usage }),
     };
     post_stream(client, base_url, &body).await
 }
@@ -146,124 +409,489 @@ pub fn create_audio_message(wav_data: &[u8]) -> ChatMessage {
 async fn post_stream(
     client: &Client,
     base_url: &str,
-    body: &ChatRequestBody,
-) -> Result<reqwest::Response, String> {
+    body: &ChatRequestBody<'_>,
+) -> Result<reqwest::Response> {
     let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
-    let res = client
+    let mut res = client
         .post(&url)
         .json(body)
         .send()
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(Error::Transport)?;
     if !res.status().is_success() {
         let status = res.status();
-        let text = res.text().await.unwrap_or_default();
-        return Err(format!("{}: {}", status, text));
+        let body = res.text().await.unwrap_or_default();
+        return Err(Error::Http { status, body });
+    }
+    if body.stream_options.is_some() {
+        res.extensions_mut().insert(UsageRequested);
     }
     Ok(res)
 }
 
-/// Process streaming response: parse NDJSON/SSE, call on_text/on_audio, return stats.
+/// Marks a response whose request asked for a usage chunk, so `process_stream` knows to read
+/// past the finish reason.
+#[derive(Clone, Copy)]
+struct UsageRequested;
+
+/// How long to wait after the finish reason for the usage chunk before giving up on it.
+const USAGE_WAIT: Duration = Duration::from_secs(1);
+
+/// Receives decoded audio from `process_stream`. Any `FnMut(&[f32])` is a sink that never
+/// pushes back; a sink whose `ready` waits (e.g. a full playback buffer) pauses reading the
+/// response, which backpressures the server instead of dropping audio.
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]);
+
+    /// Awaited after every chunk; resolves once the sink can take more.
+    fn ready(&mut self) -> impl Future<Output = ()> {
+        std::future::ready(())
+    }
+}
+
+impl<G: FnMut(&[f32])> AudioSink for G {
+    fn write(&mut self, samples: &[f32]) {
+        self(samples)
+    }
+}
+
+/// Process streaming response: decode SSE or NDJSON (see `sse`), call on_text/on_audio,
+/// return stats. Needs a Tokio runtime with the time driver enabled (the usage wait is timed).
 pub async fn process_stream<F, G>(
     res: reqwest::Response,
+    on_text: F,
+    on_audio: G,
+) -> Result<(String, StreamStats)>
+where
+    F: FnMut(&str),
+    G: FnMut(&[f32]),
+{
+    process_stream_until(res, std::future::pending(), on_text, on_audio).await
+}
+
+/// Like `process_stream`, but stops early when `cancel` resolves: the connection is dropped
+/// and the partial text and stats are returned with `cancelled` set. Audio goes to an
+/// `AudioSink`, which may apply backpressure.
+pub async fn process_stream_until<C, F, G>(
+    res: reqwest::Response,
+    cancel: C,
     mut on_text: F,
     mut on_audio: G,
-) -> Result<(String, StreamStats), String>
+) -> Result<(String, StreamStats)>
 where
+    C: Future<Output = ()>,
     F: FnMut(&str),
-    G: FnMut(&[f32]),
+    G: AudioSink,
 {
     let t0 = Instant::now();
     let mut ttft = None::<f64>;
-    let mut text_chunks: Vec<(f64, String)> = Vec::new();
-    let mut audio_chunks: Vec<(f64, usize)> = Vec::new();
+    let mut full_text = String::new();
+    let mut text_chunk_count = 0usize;
+    let mut text_span: Option<(f64, f64)> = None;
+    let mut audio_span: Option<(f64, f64)> = None;
     let mut total_samples = 0usize;
-    let mut completed = false;
-    let mut buffer = String::new();
+    let mut unparsed_events = 0usize;
+    let mut finish_reason = None::<FinishReason>;
+    let mut finish_secs = None::<f64>;
+    // Spent awaiting `on_audio.ready()`; arrival times are taken on a clock that excludes it.
+    let mut backpressure = Duration::ZERO;
+    let mut usage = None::<TokenUsage>;
+    let mut cancelled = false;
+    let mut decoder = SseDecoder::new();
+    // `[DONE]`, or the finish reason (and the usage chunk, if requested) has arrived.
+    let mut done = false;
+    let mut ended = false;
+    // Reused across chunks so steady-state decoding does not allocate.
+    let mut pcm: Vec<u8> = Vec::new();
+    let mut samples: Vec<f32> = Vec::new();
 
+    let want_usage = res.extensions().get::<UsageRequested>().is_some();
     let mut stream = res.bytes_stream();
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(|e| e.to_string())?;
-        if let Ok(s) = std::str::from_utf8(&chunk) {
-            buffer.push_str(s);
+    let mut cancel = std::pin::pin!(cancel);
+    loop {
+        let chunk = tokio::select! {
+            biased;
+            _ = &mut cancel => {
+                cancelled = true;
+                break;
+            }
+            chunk = stream.next() => chunk,
+            // Finished; only the usage chunk is outstanding and the server may hold the
+            // connection open.
+            _ = tokio::time::sleep(USAGE_WAIT), if finish_reason.is_some() => break,
+        };
+        match chunk {
+            Some(chunk) => decoder.push(&chunk.map_err(Error::Disconnected)?),
+            None => ended = true,
         }
-        while let Some(line_end) = buffer.find('\n') {
-            let line = buffer[..line_end].trim().to_string();
-            buffer = buffer[line_end + 1..].to_string();
-            let data = line.strip_prefix("data: ").unwrap_or("");
-            if data == "[DONE]" || data.is_empty() {
+        while let Some(event) = if ended { decoder.finish() } else { decoder.next_event() } {
+            // Named events are not part of the chat completion stream, except errors.
+            let error_event = event.event.as_deref() == Some("error");
+            if !event.is_message() && !error_event {
+                continue;
+            }
+            let payload = event.text();
+            let data = payload.trim();
+            if data == "[DONE]" {
+                done = true;
+                break;
+            }
+            if error_event {
+                return Err(error_event_to_error(data));
+            }
+            if data.is_empty() {
                 continue;
             }
             let chunk: StreamChunk = match serde_json::from_str(data) {
                 Ok(c) => c,
-                Err(_) => continue,
+                Err(_) => {
+                    unparsed_events += 1;
+                    continue;
+                }
             };
+            if let Some(error) = chunk.error {
+                return Err(error.into_error(data));
+            }
+            if let Some(u) = &chunk.usage {
+                usage = Some(TokenUsage::from(u));
+            }
+            // Only the event right after the finish reason may still carry usage.
+            if finish_reason.is_some() {
+                done = true;
+                break;
+            }
             let choices = match &chunk.choices {
                 Some(c) if !c.is_empty() => c,
                 _ => continue,
             };
             let choice = &choices[0];
-            if choice.finish_reason.as_deref() == Some("stop") {
-                completed = true;
-                break;
+            // The last chunk may carry a final delta along with its finish reason.
+            finish_reason = choice.finish_reason.as_deref().map(FinishReason::from);
+            let now = (t0.elapsed() - backpressure).as_secs_f64();
+            if finish_reason.is_some() {
+                finish_secs = Some(now);
             }
+            let finished = finish_reason.is_some() && (!want_usage || usage.is_some());
             let delta = match &choice.delta {
-                Some(d) => d,
-                None => continue,
+                Some(d) if finish_reason.is_none() || !d.is_empty() => d,
+                _ if finished => {
+                    done = true;
+                    break;
+                }
+                _ => continue,
             };
-            let now = t0.elapsed().as_secs_f64();
             if ttft.is_none() {
                 ttft = Some(now);
             }
             if let Some(ref text) = delta.content {
                 if !text.is_empty() {
-                    text_chunks.push((now, text.clone()));
+                    full_text.push_str(text);
+                    text_chunk_count += 1;
+                    extend_span(&mut text_span, now);
                     on_text(text);
                 }
             }
             if let Some(ref ac) = delta.audio_chunk {
-                let decoded = B64.decode(ac.data.as_bytes()).unwrap_or_default();
-                let samples: Vec<f32> = decoded
-                    .chunks_exact(4)
-                    .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
-                    .collect();
+                pcm.clear();
+                B64.decode_vec(ac.data.as_bytes(), &mut pcm)
+                    .map_err(|e| Error::Decode(format!("audio chunk: {}", e)))?;
+                samples.clear();
+                samples.extend(
+                    pcm.chunks_exact(4)
+                        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])),
+                );
                 let n = samples.len();
                 if n > 0 {
                     total_samples += n;
-                    audio_chunks.push((now, n));
-                    on_text("♪");
-                    on_audio(&samples);
+                    extend_span(&mut audio_span, now);
+                    on_text(AUDIO_MARKER);
+                    on_audio.write(&samples);
+                    let waited = Instant::now();
+                    tokio::select! {
+                        biased;
+                        _ = &mut cancel => {
+                            cancelled = true;
+                            break;
+                        }
+                        _ = on_audio.ready() => {}
+                    }
+                    backpressure += waited.elapsed();
                 }
             }
+            if finished {
+                done = true;
+                break;
+            }
         }
-        if completed {
+        if done || cancelled || ended {
             break;
         }
     }
 
     let total_secs = t0.elapsed().as_secs_f64();
-    let full_text = text_chunks.iter().map(|(_, t)| t.as_str()).collect::<String>();
-    let text_duration_secs = if text_chunks.len() > 1 {
-        text_chunks.last().map(|(t, _)| *t).unwrap_or(0.0)
-            - text_chunks.first().map(|(t, _)| *t).unwrap_or(0.0)
-    } else {
-        0.0
-    };
-    let audio_duration_secs = if audio_chunks.is_empty() {
-        0.0
-    } else {
-        let first = audio_chunks.first().map(|(t, _)| *t).unwrap_or(0.0);
-        let last = audio_chunks.last().map(|(t, _)| *t).unwrap_or(0.0);
-        last - first
-    };
+    let span_secs = |span: Option<(f64, f64)>| span.map_or(0.0, |(first, last)| last - first);
     let stats = StreamStats {
         ttft_secs: ttft,
         total_secs,
-        text_chunk_count: text_chunks.len(),
-        text_duration_secs,
+        finish_secs,
+        backpressure_secs: backpressure.as_secs_f64(),
+        text_chunk_count,
+        text_duration_secs: span_secs(text_span),
         total_audio_samples: total_samples,
-        audio_duration_secs,
-        completed,
+        audio_duration_secs: span_secs(audio_span),
+        completed: finish_reason.is_some(),
+        finish_reason,
+        cancelled,
+        unparsed_events,
+        usage,
     };
     Ok((full_text, stats))
 }
+
+/// Widen a (first, last) arrival-time span to include `now`.
+fn extend_span(span: &mut Option<(f64, f64)>, now: f64) {
+    match span {
+        Some((_, last)) => *last = now,
+        None => *span = Some((now, now)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures_util::stream;
+
+    /// Response streaming `events` as SSE; with `hold_open` the connection then stays open
+    /// instead of ending.
+    fn sse_response(
+        events: &[serde_json::Value],
+        hold_open: bool,
+        usage: bool,
+    ) -> reqwest::Response {
+        let chunks: Vec<std::io::Result<Bytes>> = events
+            .iter()
+            .map(|e| Ok(Bytes::from(format!("data: {}\n\n", e))))
+            .collect();
+        let body = stream::iter(chunks);
+        let body = if hold_open {
+            reqwest::Body::wrap_stream(body.chain(stream::pending()))
+        } else {
+            reqwest::Body::wrap_stream(body)
+        };
+        let mut res = http::Response::new(body);
+        if usage {
+            res.extensions_mut().insert(UsageRequested);
+        }
+        reqwest::Response::from(res)
+    }
+
+    fn raw_response(body: &'static str) -> reqwest::Response {
+        reqwest::Response::from(http::Response::new(reqwest::Body::from(body)))
+    }
+
+    fn server_error(result: Result<(String, StreamStats)>) -> (String, Option<String>) {
+        match result {
+            Err(Error::Server { message, code }) => (message, code),
+            Err(e) => panic!("expected a server error, got {}", e),
+            Ok((text, _)) => panic!("expected a server error, got reply {:?}", text),
+        }
+    }
+
+    fn text(content: &str) -> serde_json::Value {
+        serde_json::json!({"choices": [{"delta": {"content": content}}]})
+    }
+
+    fn finish(reason: &str) -> serde_json::Value {
+        serde_json::json!({"choices": [{"delta": {}, "finish_reason": reason}]})
+    }
+
+    fn usage_chunk() -> serde_json::Value {
+        serde_json::json!({
+            "choices": [],
+            "usage": {
+                "prompt_tokens": 12,
+                "completion_tokens": 40,
+                "completion_tokens_details": {"audio_tokens": 30}
+            }
+        })
+    }
+
+    async fn run(res: reqwest::Response) -> Result<(String, StreamStats)> {
+        let decode = process_stream(res, |_| {}, |_| {});
+        tokio::time::timeout(Duration::from_secs(5), decode)
+            .await
+            .expect("process_stream did not return")
+    }
+
+    #[tokio::test]
+    async fn stops_at_finish_reason_without_usage() {
+        let res = sse_response(&[text("hi"), finish("stop")], true, false);
+        let started = Instant::now();
+        let (text, stats) = run(res).await.unwrap();
+        assert!(started.elapsed() < USAGE_WAIT);
+        assert_eq!(text, "hi");
+        assert_eq!(stats.finish_reason, Some(FinishReason::Stop));
+        assert_eq!(stats.usage, None);
+    }
+
+    #[tokio::test]
+    async fn reads_usage_after_finish_reason() {
+        let res = sse_response(&[text("hi"), finish("stop"), usage_chunk()], true, true);
+        let (_, stats) = run(res).await.unwrap();
+        let usage = stats.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 40));
+        assert_eq!(usage.completion_audio_tokens, Some(30));
+        assert_eq!(usage.prompt_audio_tokens, None);
+        assert!(stats.completed);
+    }
+
+    #[tokio::test]
+    async fn usage_wait_is_bounded() {
+        let res = sse_response(&[text("hi"), finish("stop")], true, true);
+        let (_, stats) = run(res).await.unwrap();
+        assert!(stats.completed);
+        assert_eq!(stats.usage, None);
+    }
+
+    #[tokio::test]
+    async fn only_the_event_after_finish_may_carry_usage() {
+        let events = [finish("stop"), text("late"), usage_chunk()];
+        let (text, stats) = run(sse_response(&events, true, true)).await.unwrap();
+        assert_eq!(text, "");
+        assert_eq!(stats.usage, None);
+    }
+
+    #[tokio::test]
+    async fn length_and_content_filter_are_reported() {
+        let events = [text("cut"), finish("length")];
+        let (reply, stats) = run(sse_response(&events, false, false)).await.unwrap();
+        assert_eq!(reply, "cut");
+        assert_eq!(stats.finish_reason, Some(FinishReason::Length));
+        assert!(stats.completed);
+
+        let events = [text("no"), finish("content_filter")];
+        let (_, stats) = run(sse_response(&events, true, false)).await.unwrap();
+        assert_eq!(stats.finish_reason, Some(FinishReason::ContentFilter));
+        assert!(stats.completed && !stats.cancelled);
+    }
+
+    #[tokio::test]
+    async fn final_chunk_may_carry_text_and_finish_reason() {
+        let last = serde_json::json!({
+            "choices": [{"delta": {"content": " there"}, "finish_reason": "length"}]
+        });
+        let (reply, stats) = run(sse_response(&[text("hi"), last], true, false)).await.unwrap();
+        assert_eq!(reply, "hi there");
+        assert_eq!(stats.text_chunk_count, 2);
+        assert_eq!(stats.finish_reason, Some(FinishReason::Length));
+    }
+
+    fn audio(samples: &[f32]) -> serde_json::Value {
+        let pcm: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        serde_json::json!({"choices": [{"delta": {"audio_chunk": {"data": B64.encode(pcm)}}}]})
+    }
+
+    /// Sink that takes 100 ms to drain each chunk, like playback at real time.
+    struct SlowSink;
+
+    impl AudioSink for SlowSink {
+        fn write(&mut self, _: &[f32]) {}
+
+        async fn ready(&mut self) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn rates_exclude_sink_backpressure() {
+        let chunk = [0.25f32; 240];
+        let events = [
+            text("hi"),
+            audio(&chunk),
+            audio(&chunk),
+            audio(&chunk),
+            audio(&chunk),
+            finish("stop"),
+            usage_chunk(),
+        ];
+        let decode = process_stream_until(
+            sse_response(&events, false, true),
+            std::future::pending(),
+            |_| {},
+            SlowSink,
+        );
+        let (_, stats) = tokio::time::timeout(Duration::from_secs(5), decode)
+            .await
+            .expect("process_stream_until did not return")
+            .unwrap();
+        assert_eq!(stats.total_audio_samples, 4 * chunk.len());
+        assert!(stats.backpressure_secs >= 0.4, "{:?}", stats);
+        assert!(stats.total_secs >= stats.backpressure_secs);
+        // Arrival times run on a clock that stops while the sink is full.
+        assert!(stats.audio_duration_secs < 0.2, "{:?}", stats);
+        let generation = stats.finish_secs.unwrap() - stats.ttft_secs.unwrap();
+        assert!(generation < 0.2, "{:?}", stats);
+        assert!(stats.tokens_per_sec().unwrap() > 40.0 / 0.2);
+    }
+
+    #[tokio::test]
+    async fn embedded_error_object_fails_the_stream() {
+        let error = serde_json::json!({
+            "error": {"message": "CUDA out of memory", "type": "server_error", "code": "oom"}
+        });
+        let res = sse_response(&[text("Partial"), error], true, false);
+        let (message, code) = server_error(run(res).await);
+        assert_eq!(message, "CUDA out of memory");
+        assert_eq!(code.as_deref(), Some("oom"));
+    }
+
+    #[tokio::test]
+    async fn numeric_code_and_type_fallback() {
+        let numeric = serde_json::json!({"error": {"message": "boom", "code": 500}});
+        let (_, code) = server_error(run(sse_response(&[numeric], false, false)).await);
+        assert_eq!(code.as_deref(), Some("500"));
+
+        let typed = serde_json::json!({"error": {"message": "boom", "type": "overloaded"}});
+        let (_, code) = server_error(run(sse_response(&[typed], false, false)).await);
+        assert_eq!(code.as_deref(), Some("overloaded"));
+    }
+
+    #[tokio::test]
+    async fn every_error_event_fails_the_stream() {
+        let bare =
+            raw_response("event: error\ndata: {\"message\":\"overloaded\",\"code\":503}\n\n");
+        assert_eq!(
+            server_error(run(bare).await),
+            ("overloaded".to_string(), Some("503".to_string()))
+        );
+
+        let wrapped = raw_response("event: error\ndata: {\"error\":{\"message\":\"bad\"}}\n\n");
+        assert_eq!(server_error(run(wrapped).await), ("bad".to_string(), None));
+
+        let other_json = raw_response("event: error\ndata: {\"status\":\"down\"}\n\n");
+        assert_eq!(
+            server_error(run(other_json).await),
+            ("{\"status\":\"down\"}".to_string(), None)
+        );
+
+        let plain = raw_response("event: error\ndata: model crashed\n\n");
+        assert_eq!(server_error(run(plain).await), ("model crashed".to_string(), None));
+    }
+
+    #[tokio::test]
+    async fn unparsed_events_are_counted_and_skipped() {
+        let res = raw_response(concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"a\"}}]}\n\n",
+            "data: {not json\n\n",
+            "data: [1, 2\n\n",
+            ": comment\n\n",
+            "event: ping\ndata: nope\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"b\"},\"finish_reason\":\"stop\"}]}\n\n",
+        ));
+        let (text, stats) = run(res).await.unwrap();
+        assert_eq!(text, "ab");
+        assert_eq!(stats.unparsed_events, 2);
+        assert!(stats.completed);
+    }
+}
@@ -1,194 +1,1162 @@
-//! Low-latency audio I/O via cpal. Playback uses a lock-free channel fed by the stream.
+//! Low-latency audio I/O via cpal. Playback goes through a ring buffer fed by the stream; the
+//! output callback reads it without locking or allocating.
 
-use crossbeam_channel::{bounded, Sender};
+use crate::api::AudioSink;
+use crate::error::{Error, Result};
+use crate::ring::{RingReader, SampleRing};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::StreamConfig;
-use hound::{WavSpec, WavWriter};
+use hound::{WavReader, WavSpec, WavWriter};
 use std::cell::RefCell;
 use std::io::Cursor;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
-const PLAYBACK_SAMPLE_RATE: u32 = 24000;
-const RECORD_SAMPLE_RATE: u32 = 16000;
+pub const PLAYBACK_SAMPLE_RATE: u32 = 24000;
+pub const RECORD_SAMPLE_RATE: u32 = 16000;
 const CHANNELS: u16 = 1;
-const QUEUE_CAPACITY: usize = 64;
+/// Length of the fade applied when playback runs dry and when it resumes.
+const FADE_MS: u32 = 5;
+/// Pre-roll growth after an underrun, and its decay after a response without one.
+const PREROLL_GROWTH: f64 = 1.5;
+const PREROLL_RELAX: f64 = 0.9;
+/// Ceiling for the adaptive pre-roll (unless the configured one is larger).
+const MAX_PREROLL_MS: u32 = 1000;
+/// Samples the output callback takes from the ring per resampler pass.
+const OUTPUT_READ_BLOCK: usize = 512;
+const RECORD_POLL_INTERVAL: Duration = Duration::from_millis(20);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Extra time a drain may take beyond the queued audio and device latency (pre-roll, fades,
+/// callback scheduling) before the output is considered stalled.
+const DRAIN_SLACK: Duration = Duration::from_millis(500);
+const VAD_FRAME_MS: u32 = 20;
+/// Noise floor never tracks below this, so digital silence doesn't make hiss look like speech.
+const VAD_MIN_FLOOR_DB: f32 = -60.0;
+/// Fraction of sign changes per sample typical of unvoiced speech (s, f, sh).
+const VAD_UNVOICED_ZCR: f32 = 0.25;
+/// Assumed speaker-to-microphone loss: mic frames quieter than playback minus this are echo.
+const ECHO_ATTENUATION_DB: f32 = 10.0;
+/// How fast the remembered playback level decays, covering output/input latency.
+const ECHO_HOLD_DECAY_DB_PER_FRAME: f32 = 1.0;
+/// Continuous speech needed to interrupt playback (longer than a normal VAD start).
+const BARGE_IN_MIN_SPEECH_MS: u32 = 200;
+/// Input sample rates accepted from files. Outside this, a header is corrupt or hostile: zero
+/// stalls the resampler and huge rates blow up its polyphase table.
+const MIN_INPUT_SAMPLE_RATE: u32 = 1_000;
+const MAX_INPUT_SAMPLE_RATE: u32 = 384_000;
+/// Sinc zero crossings on each side of the resampling kernel.
+const SINC_ZERO_CROSSINGS: f64 = 16.0;
+
+/// State shared between the player, its handles and the output callback.
+struct PlaybackShared {
+    /// Cleared by `stop`, and by the output stream when its device goes away.
+    running: AtomicBool,
+    flush: AtomicBool,
+    /// RMS of the last output buffer, as `f32` bits.
+    level: AtomicU32,
+    /// 24 kHz samples waiting for the output callback (which owns the reading end).
+    ring: SampleRing,
+    /// Resampled samples the callback holds but has not played yet.
+    buffered: AtomicUsize,
+    device_rate: u32,
+    /// Device latency (callback to speaker) reported by the last callback, in microseconds.
+    latency_us: AtomicU32,
+    /// Set while a `PlaybackSink` has started receiving audio; running dry then is an underrun.
+    streaming: AtomicBool,
+    underruns: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Playback health since the last `PlaybackHandle::take_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlaybackStats {
+    /// Times the output ran dry while a response was still streaming.
+    pub underruns: u64,
+    /// Samples discarded by `add_samples` because the buffer was full.
+    pub dropped_samples: u64,
+}
 
 /// Send-safe handle to push samples from async/other threads.
 #[derive(Clone)]
-pub struct PlaybackHandle(Arc<Sender<Vec<f32>>>);
+pub struct PlaybackHandle {
+    shared: Arc<PlaybackShared>,
+}
 
 impl PlaybackHandle {
+    /// Queue samples without waiting; whatever does not fit is dropped (and counted).
+    /// Use `push` or a `PlaybackSink` to wait for room instead.
     #[inline]
     pub fn add_samples(&self, samples: &[f32]) {
-        if !samples.is_empty() {
-            let _ = self.0.try_send(samples.to_vec());
+        let written = self.shared.ring.push(samples);
+        let dropped = (samples.len() - written) as u64;
+        if dropped > 0 {
+            self.shared.dropped.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+
+    /// Queue all of `samples`, waiting for the callback to make room when the buffer is full.
+    /// Gives up (dropping the rest) if the player is stopped.
+    pub async fn push(&self, mut samples: &[f32]) {
+        loop {
+            samples = &samples[self.shared.ring.push(samples)..];
+            if samples.is_empty() {
+                return;
+            }
+            if !self.shared.running.load(Ordering::Relaxed) {
+                self.shared.dropped.fetch_add(samples.len() as u64, Ordering::Relaxed);
+                return;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Counters since the previous call (or since the player opened), then reset them.
+    pub fn take_stats(&self) -> PlaybackStats {
+        PlaybackStats {
+            underruns: self.shared.underruns.swap(0, Ordering::Relaxed),
+            dropped_samples: self.shared.dropped.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    /// A lossless, backpressuring `AudioSink` feeding this player.
+    pub fn sink(&self) -> PlaybackSink {
+        PlaybackSink {
+            handle: self.clone(),
+            overflow: Vec::new(),
+        }
+    }
+
+    /// Drop everything queued; the output goes silent on the next callback.
+    pub fn flush(&self) {
+        self.shared.flush.store(true, Ordering::Relaxed);
+    }
+
+    /// Level of what the speakers are playing right now, in dBFS.
+    pub fn output_level_db(&self) -> f32 {
+        let rms = f32::from_bits(self.shared.level.load(Ordering::Relaxed));
+        20.0 * rms.max(1e-6).log10()
+    }
+
+    /// False once the player has stopped: closed, stalled, or its device went away. A stopped
+    /// player does not restart; open a new one.
+    pub fn is_running(&self) -> bool {
+        self.shared.running.load(Ordering::Relaxed)
+    }
+
+    /// True once everything added has been handed to the device (or the player is stopped).
+    pub fn is_empty(&self) -> bool {
+        let shared = &self.shared;
+        !shared.running.load(Ordering::Relaxed)
+            || (shared.ring.len() == 0
+                && shared.buffered.load(Ordering::Relaxed) == 0
+                && !shared.flush.load(Ordering::Relaxed))
+    }
+
+    /// Resolves once everything added so far has been played, including the device's own
+    /// buffering. If the output stops consuming audio, gives up after the queued duration plus
+    /// latency and stops the player.
+    pub async fn wait_until_empty(&self) {
+        let deadline = self.drain_deadline();
+        while !self.is_empty() {
+            if Instant::now() >= deadline {
+                return self.stalled();
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        tokio::time::sleep(self.output_latency()).await;
+    }
+
+    /// Blocking version of `wait_until_empty`.
+    pub fn drain(&self) {
+        let deadline = self.drain_deadline();
+        while !self.is_empty() {
+            if Instant::now() >= deadline {
+                return self.stalled();
+            }
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+        thread::sleep(self.output_latency());
+    }
+
+    fn output_latency(&self) -> Duration {
+        Duration::from_micros(self.shared.latency_us.load(Ordering::Relaxed) as u64)
+    }
+
+    /// When everything queued now should have played, with `DRAIN_SLACK` to spare.
+    fn drain_deadline(&self) -> Instant {
+        let shared = &self.shared;
+        let queued = Duration::from_secs_f64(
+            shared.ring.len() as f64 / PLAYBACK_SAMPLE_RATE as f64
+                + shared.buffered.load(Ordering::Relaxed) as f64 / shared.device_rate as f64,
+        );
+        Instant::now() + queued + self.output_latency() + DRAIN_SLACK
+    }
+
+    /// The callback stopped taking audio: stop the player so `push` and later drains return.
+    fn stalled(&self) {
+        if self.shared.running.swap(false, Ordering::Relaxed) {
+            eprintln!("audio output stalled; playback stopped");
         }
     }
 }
 
-/// Non-blocking audio player. Streams f32 mono at 24 kHz. Not Send (cpal stream).
+/// Playback buffering settings.
+#[derive(Clone, Copy, Debug)]
+pub struct PlaybackConfig {
+    /// Ring buffer size; when it is full a `PlaybackSink` holds back the HTTP stream.
+    pub buffer_ms: u32,
+    /// Audio to collect before starting (and restarting after an underrun). Grows after
+    /// underruns, shrinks back towards this after smooth responses.
+    pub preroll_ms: u32,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        Self {
+            buffer_ms: 10_000,
+            preroll_ms: 100,
+        }
+    }
+}
+
+/// Streams response audio into the player without dropping any: what does not fit is held
+/// and `ready` waits for room, pausing the HTTP stream. Underruns are counted while it is alive.
+pub struct PlaybackSink {
+    handle: PlaybackHandle,
+    overflow: Vec<f32>,
+}
+
+impl AudioSink for PlaybackSink {
+    fn write(&mut self, samples: &[f32]) {
+        self.handle.shared.streaming.store(true, Ordering::Relaxed);
+        let rest = if self.overflow.is_empty() {
+            &samples[self.handle.shared.ring.push(samples)..]
+        } else {
+            samples
+        };
+        self.overflow.extend_from_slice(rest);
+    }
+
+    async fn ready(&mut self) {
+        if !self.overflow.is_empty() {
+            self.handle.push(&self.overflow).await;
+            self.overflow.clear();
+        }
+    }
+}
+
+impl Drop for PlaybackSink {
+    fn drop(&mut self) {
+        self.handle.shared.streaming.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Which audio host and devices to use. `None` means the host's default. Devices are given by
+/// name (exact, else a unique case-insensitive substring) or by index from `list_devices`.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceSelection {
+    pub host: Option<String>,
+    pub input: Option<String>,
+    pub output: Option<String>,
+}
+
+/// Devices offered by one audio host, in index order.
+#[derive(Clone, Debug)]
+pub struct DeviceList {
+    pub host: String,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub default_input: Option<String>,
+    pub default_output: Option<String>,
+}
+
+/// Names of the audio hosts (backends) compiled in and available on this system.
+pub fn available_hosts() -> Vec<&'static str> {
+    cpal::available_hosts().into_iter().map(|id| id.name()).collect()
+}
+
+/// Name of the host `name` selects (or the default host), without enumerating its devices.
+pub fn host_name(name: Option<&str>) -> Result<&'static str> {
+    open_host(name).map(|host| host.id().name())
+}
+
+/// Devices of the selected host (or the default host).
+pub fn list_devices(host: Option<&str>) -> Result<DeviceList> {
+    let host = open_host(host)?;
+    let inputs = host.input_devices().map_err(Error::audio)?;
+    let outputs = host.output_devices().map_err(Error::audio)?;
+    Ok(DeviceList {
+        host: host.id().name().to_string(),
+        inputs: inputs.map(|d| device_name(&d)).collect(),
+        outputs: outputs.map(|d| device_name(&d)).collect(),
+        default_input: host.default_input_device().map(|d| device_name(&d)),
+        default_output: host.default_output_device().map(|d| device_name(&d)),
+    })
+}
+
+impl DeviceSelection {
+    /// Name of the input device this selection resolves to.
+    pub fn input_device_name(&self) -> Result<String> {
+        self.input_device().map(|d| device_name(&d))
+    }
+
+    /// Name of the output device this selection resolves to.
+    pub fn output_device_name(&self) -> Result<String> {
+        self.output_device().map(|d| device_name(&d))
+    }
+
+    fn input_device(&self) -> Result<cpal::Device> {
+        let host = open_host(self.host.as_deref())?;
+        match self.input.as_deref() {
+            None => host
+                .default_input_device()
+                .ok_or_else(|| Error::audio("no default input device")),
+            Some(spec) => find_device(host.input_devices().map_err(Error::audio)?, spec, "input"),
+        }
+    }
+
+    fn output_device(&self) -> Result<cpal::Device> {
+        let host = open_host(self.host.as_deref())?;
+        match self.output.as_deref() {
+            None => host
+                .default_output_device()
+                .ok_or_else(|| Error::audio("no default output device")),
+            Some(spec) => find_device(host.output_devices().map_err(Error::audio)?, spec, "output"),
+        }
+    }
+}
+
+fn open_host(name: Option<&str>) -> Result<cpal::Host> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            Error::AudioDevice(format!(
+                "unknown audio host '{}' (available: {})",
+                name,
+                available_hosts().join(", ")
+            ))
+        })?;
+    cpal::host_from_id(id).map_err(Error::audio)
+}
+
+fn device_name(device: &cpal::Device) -> String {
+    device.name().unwrap_or_else(|_| "<unnamed>".to_string())
+}
+
+/// Resolve `spec` as an index, an exact name, or a unique case-insensitive substring.
+fn find_device(
+    devices: impl Iterator<Item = cpal::Device>,
+    spec: &str,
+    kind: &str,
+) -> Result<cpal::Device> {
+    let devices: Vec<(String, cpal::Device)> = devices.map(|d| (device_name(&d), d)).collect();
+    let not_found = || Error::AudioDevice(format!("no {} device matches '{}'", kind, spec));
+    if let Ok(index) = spec.parse::<usize>() {
+        return devices.into_iter().nth(index).map(|(_, d)| d).ok_or_else(not_found);
+    }
+    if let Some(i) = devices.iter().position(|(name, _)| name == spec) {
+        return Ok(devices.into_iter().nth(i).map(|(_, d)| d).expect("index in range"));
+    }
+    let needle = spec.to_lowercase();
+    let mut matches: Vec<_> = devices
+        .into_iter()
+        .filter(|(name, _)| name.to_lowercase().contains(&needle))
+        .collect();
+    match matches.len() {
+        0 => Err(not_found()),
+        1 => Ok(matches.remove(0).1),
+        _ => Err(Error::AudioDevice(format!(
+            "'{}' matches several {} devices: {}",
+            spec,
+            kind,
+            matches.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
+
+/// Pick a device config near `rate`: the requested rate if supported (fewest channels), else
+/// the device's default, else the closest rate it offers. Among equal candidates f32 is
+/// preferred, then the integer formats. The stream callbacks convert the sample format,
+/// resample and map channels to and from the mono f32 pipeline.
+fn negotiate_config(
+    ranges: Vec<cpal::SupportedStreamConfigRange>,
+    default: Option<cpal::SupportedStreamConfig>,
+    rate: u32,
+) -> Result<(StreamConfig, cpal::SampleFormat)> {
+    let usable: Vec<_> = ranges
+        .into_iter()
+        .filter(|r| format_rank(r.sample_format()).is_some())
+        .collect();
+    let rank = |r: &cpal::SupportedStreamConfigRange| format_rank(r.sample_format());
+    let exact = usable
+        .iter()
+        .filter(|r| (r.min_sample_rate().0..=r.max_sample_rate().0).contains(&rate))
+        .min_by_key(|r| (rank(r), r.channels()));
+    let chosen = match (exact, default) {
+        (Some(range), _) => range.with_sample_rate(cpal::SampleRate(rate)),
+        (None, Some(default)) if format_rank(default.sample_format()).is_some() => default,
+        (None, _) => {
+            let range = usable
+                .iter()
+                .min_by_key(|r| (r.min_sample_rate().0.saturating_sub(rate), rank(r), r.channels()))
+                .ok_or_else(|| Error::audio("device offers no supported sample format"))?;
+            let rate = rate.clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+            range.with_sample_rate(cpal::SampleRate(rate))
+        }
+    };
+    let mut config = chosen.config();
+    config.buffer_size = cpal::BufferSize::Default;
+    Ok((config, chosen.sample_format()))
+}
+
+/// Preference order of the sample formats the stream builders handle; `None` if unsupported.
+fn format_rank(format: cpal::SampleFormat) -> Option<u8> {
+    use cpal::SampleFormat::*;
+    match format {
+        F32 => Some(0),
+        I16 => Some(1),
+        I32 => Some(2),
+        F64 => Some(3),
+        U16 => Some(4),
+        I8 => Some(5),
+        U8 => Some(6),
+        U32 => Some(7),
+        I64 => Some(8),
+        U64 => Some(9),
+        _ => None,
+    }
+}
+
+/// Dispatch on a runtime `SampleFormat` to a function generic over the matching sample type.
+macro_rules! with_sample_type {
+    ($format:expr, $build:ident($($arg:expr),*)) => {{
+        use cpal::SampleFormat::*;
+        match $format {
+            F32 => $build::<f32>($($arg),*),
+            I16 => $build::<i16>($($arg),*),
+            I32 => $build::<i32>($($arg),*),
+            F64 => $build::<f64>($($arg),*),
+            U16 => $build::<u16>($($arg),*),
+            I8 => $build::<i8>($($arg),*),
+            U8 => $build::<u8>($($arg),*),
+            U32 => $build::<u32>($($arg),*),
+            I64 => $build::<i64>($($arg),*),
+            U64 => $build::<u64>($($arg),*),
+            other => Err(Error::AudioDevice(format!("unsupported sample format {}", other))),
+        }
+    }};
+}
+
+/// Write one mono sample to an output frame: front left/right (or the only channel); any
+/// further channels (centre, LFE, surrounds) stay silent.
+#[inline]
+fn map_mono_to_frame(sample: f32, frame: &mut [f32]) {
+    let (front, rest) = frame.split_at_mut(frame.len().min(2));
+    front.fill(sample);
+    rest.fill(0.0);
+}
+
+/// Non-blocking audio player. Takes f32 mono at 24 kHz through a ring buffer and
+/// converts to whatever sample format, rate and channel count the device runs at. Meant to be
+/// opened once and reused across responses; `drain` / `wait_until_empty` wait for queued audio
+/// to finish. Not Send (cpal stream).
 pub struct AudioPlayer {
-    tx: Arc<Sender<Vec<f32>>>,
-    running: Arc<AtomicBool>,
-    _stream_guard: Arc<Mutex<Option<cpal::Stream>>>,
+    handle: PlaybackHandle,
+    stream: RefCell<Option<cpal::Stream>>,
+    device_name: String,
 }
 
 impl AudioPlayer {
-    pub fn new() -> Result<Self, String> {
-        let (tx, rx) = bounded::<Vec<f32>>(QUEUE_CAPACITY);
-        let running = Arc::new(AtomicBool::new(true));
-
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or("no default output device")?;
-
-        let config = StreamConfig {
-            channels: CHANNELS,
-            sample_rate: cpal::SampleRate(PLAYBACK_SAMPLE_RATE),
-            buffer_size: cpal::BufferSize::Default,
+    /// Play on the default output device.
+    pub fn new() -> Result<Self> {
+        Self::open(&DeviceSelection::default(), PlaybackConfig::default())
+    }
+
+    /// Play on the output device picked by `devices`.
+    pub fn open(devices: &DeviceSelection, playback: PlaybackConfig) -> Result<Self> {
+        let device = devices.output_device()?;
+        let (config, format) = negotiate_config(
+            device.supported_output_configs().map_err(Error::audio)?.collect(),
+            device.default_output_config().ok(),
+            PLAYBACK_SAMPLE_RATE,
+        )?;
+        let device_rate = config.sample_rate.0;
+
+        let capacity = (PLAYBACK_SAMPLE_RATE as u64 * playback.buffer_ms as u64 / 1000) as usize;
+        let (ring, reader) = SampleRing::new(capacity);
+        let handle = PlaybackHandle {
+            shared: Arc::new(PlaybackShared {
+                running: AtomicBool::new(true),
+                flush: AtomicBool::new(false),
+                level: AtomicU32::new(0),
+                ring,
+                buffered: AtomicUsize::new(0),
+                device_rate,
+                latency_us: AtomicU32::new(0),
+                streaming: AtomicBool::new(false),
+                underruns: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+            }),
         };
+        let output = OutputState {
+            shared: Arc::clone(&handle.shared),
+            reader,
+            input: vec![0.0; OUTPUT_READ_BLOCK],
+            jitter: JitterBuffer::new(playback, device_rate),
+            rate_ratio: device_rate as f64 / PLAYBACK_SAMPLE_RATE as f64,
+            channels: config.channels as usize,
+            resampler: StreamResampler::new(PLAYBACK_SAMPLE_RATE, config.sample_rate.0),
+            ready: Vec::new(),
+            ready_pos: 0,
+        };
+        let stream = with_sample_type!(format, build_output_stream(&device, &config, output))?;
 
-        let run = Arc::clone(&running);
-        let leftover: RefCell<Option<(Vec<f32>, usize)>> = RefCell::new(None);
-        let stream = device
-            .build_output_stream(
-                &config,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                    if !run.load(Ordering::Relaxed) {
-                        return;
-                    }
-                    let mut written = 0;
-                    // Drain leftover from previous callback
-                    {
-                        let mut left = leftover.borrow_mut();
-                        if let Some((ref v, ref mut offset)) = *left {
-                            let take = (data.len() - written).min(v.len() - *offset);
-                            data[written..written + take].copy_from_slice(&v[*offset..*offset + take]);
-                            written += take;
-                            *offset += take;
-                            if *offset >= v.len() {
-                                *left = None;
-                            }
-                        }
-                    }
-                    while written < data.len() {
-                        match rx.try_recv() {
-                            Ok(mut chunk) => {
-                                let need = data.len() - written;
-                                let take = need.min(chunk.len());
-                                data[written..written + take].copy_from_slice(&chunk[..take]);
-                                written += take;
-                                if take < chunk.len() {
-                                    chunk.drain(..take);
-                                    *leftover.borrow_mut() = Some((chunk, 0));
-                                    break;
-                                }
-                            }
-                            Err(_) => break,
-                        }
-                    }
-                    if written < data.len() {
-                        data[written..].fill(0.0);
-                    }
-                },
-                move |e| eprintln!("audio output error: {}", e),
-                None,
-            )
-            .map_err(|e| e.to_string())?;
-
-        stream.play().map_err(|e| e.to_string())?;
+        stream.play().map_err(Error::audio)?;
 
         Ok(Self {
-            tx: Arc::new(tx),
-            running,
-            _stream_guard: Arc::new(Mutex::new(Some(stream))),
+            handle,
+            stream: RefCell::new(Some(stream)),
+            device_name: device_name(&device),
         })
     }
 
+    /// Name of the output device playing.
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
     /// Handle that can be sent to async tasks for feeding audio.
     #[inline]
     pub fn handle(&self) -> PlaybackHandle {
-        PlaybackHandle(Arc::clone(&self.tx))
+        self.handle.clone()
+    }
+
+    /// Block until everything queued has been played.
+    pub fn drain(&self) {
+        self.handle.drain();
+    }
+
+    /// Resolves once everything queued has been played.
+    pub async fn wait_until_empty(&self) {
+        self.handle.wait_until_empty().await;
+    }
+
+    /// See `PlaybackHandle::is_running`.
+    pub fn is_running(&self) -> bool {
+        self.handle.is_running()
     }
 
     pub fn stop(&self) {
-        self.running.store(false, Ordering::Relaxed);
-        let _ = self._stream_guard.lock().unwrap().take();
+        self.handle.shared.running.store(false, Ordering::Relaxed);
+        let _ = self.stream.borrow_mut().take();
+    }
+}
+
+/// Handles outlive the player; stopping releases anything waiting on them.
+impl Drop for AudioPlayer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Everything the output callback owns; works in f32 whatever the device format.
+struct OutputState {
+    shared: Arc<PlaybackShared>,
+    /// The ring's only reader.
+    reader: RingReader,
+    /// Scratch for samples taken from the ring before resampling.
+    input: Vec<f32>,
+    jitter: JitterBuffer,
+    /// Device samples per 24 kHz sample.
+    rate_ratio: f64,
+    channels: usize,
+    resampler: StreamResampler,
+    /// Mono samples at the device rate, not yet played.
+    ready: Vec<f32>,
+    ready_pos: usize,
+}
+
+impl OutputState {
+    fn fill(&mut self, data: &mut [f32]) {
+        let shared = &*self.shared;
+        if !shared.running.load(Ordering::Relaxed) {
+            data.fill(0.0);
+            return;
+        }
+        if shared.flush.swap(false, Ordering::Relaxed) {
+            self.reader.clear();
+            self.ready.clear();
+            self.ready_pos = 0;
+            self.resampler.reset();
+            self.jitter.rebuffer();
+        }
+        let streaming = shared.streaming.load(Ordering::Relaxed);
+        self.jitter.track_stream(streaming);
+        let frames = data.len() / self.channels;
+        let queued = self.ready.len() - self.ready_pos
+            + (self.reader.len() as f64 * self.rate_ratio) as usize;
+        let mut take = 0;
+        if self.jitter.may_play(queued, streaming) {
+            while self.ready.len() - self.ready_pos < frames {
+                let n = self.reader.pop(&mut self.input);
+                if n == 0 {
+                    break;
+                }
+                self.ready.drain(..self.ready_pos);
+                self.ready_pos = 0;
+                self.resampler.process(&self.input[..n], &mut self.ready);
+            }
+            take = (self.ready.len() - self.ready_pos).min(frames);
+            if take < frames && self.jitter.ran_dry(streaming) {
+                shared.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let playing = &self.ready[self.ready_pos..self.ready_pos + take];
+        let dry = take < frames;
+        let frames_out = data.chunks_exact_mut(self.channels).zip(playing);
+        for (i, (frame, &sample)) in frames_out.enumerate() {
+            map_mono_to_frame(sample * self.jitter.gain(i, take, dry), frame);
+        }
+        data[take * self.channels..].fill(0.0);
+        self.ready_pos += take;
+        shared
+            .buffered
+            .store(self.ready.len() - self.ready_pos, Ordering::Relaxed);
+        let energy = data.iter().map(|s| s * s).sum::<f32>() / data.len().max(1) as f32;
+        shared.level.store(energy.sqrt().to_bits(), Ordering::Relaxed);
     }
 }
 
-/// Record from default microphone, convert to WAV bytes.
+/// Pre-roll and fades for the output callback. Playback starts once `preroll` samples are
+/// queued (or the stream has ended); running dry mid-stream fades out, counts an underrun,
+/// raises the pre-roll and re-buffers; resuming fades back in.
+struct JitterBuffer {
+    base_preroll: usize,
+    max_preroll: usize,
+    preroll: usize,
+    buffering: bool,
+    fade_len: usize,
+    /// Samples of fade-in still to apply after (re)starting.
+    fade_in_left: usize,
+    was_streaming: bool,
+    underrun_in_stream: bool,
+}
+
+impl JitterBuffer {
+    fn new(config: PlaybackConfig, rate: u32) -> Self {
+        let samples = |ms: u32| (rate as u64 * ms as u64 / 1000) as usize;
+        // Never wait for more than half the ring, or a full ring could stall both sides.
+        let limit = samples(config.buffer_ms / 2);
+        let base_preroll = samples(config.preroll_ms).min(limit);
+        Self {
+            base_preroll,
+            max_preroll: samples(MAX_PREROLL_MS).max(base_preroll).min(limit),
+            preroll: base_preroll,
+            buffering: true,
+            fade_len: samples(FADE_MS).max(1),
+            fade_in_left: 0,
+            was_streaming: false,
+            underrun_in_stream: false,
+        }
+    }
+
+    /// Relax the pre-roll when a response finishes without underruns.
+    fn track_stream(&mut self, streaming: bool) {
+        if self.was_streaming && !streaming {
+            if !self.underrun_in_stream {
+                let relaxed = (self.preroll as f64 * PREROLL_RELAX) as usize;
+                self.preroll = relaxed.max(self.base_preroll);
+            }
+            self.underrun_in_stream = false;
+        }
+        self.was_streaming = streaming;
+    }
+
+    /// Whether to play this callback, given `queued` samples at the device rate.
+    fn may_play(&mut self, queued: usize, streaming: bool) -> bool {
+        if self.buffering && (queued >= self.preroll.max(1) || (!streaming && queued > 0)) {
+            self.buffering = false;
+            self.fade_in_left = self.fade_len;
+        }
+        !self.buffering
+    }
+
+    /// The queue emptied mid-callback. Returns true if that was an underrun (more audio was
+    /// still expected), which also raises the pre-roll.
+    fn ran_dry(&mut self, streaming: bool) -> bool {
+        self.rebuffer();
+        if streaming {
+            self.underrun_in_stream = true;
+            let grown = (self.preroll.max(self.fade_len) as f64 * PREROLL_GROWTH) as usize;
+            self.preroll = grown.min(self.max_preroll);
+        }
+        streaming
+    }
+
+    fn rebuffer(&mut self) {
+        self.buffering = true;
+    }
+
+    /// Gain for sample `i` of `take` played this callback; `dry` fades out the end.
+    fn gain(&mut self, i: usize, take: usize, dry: bool) -> f32 {
+        let mut gain = 1.0;
+        if self.fade_in_left > 0 {
+            gain *= 1.0 - self.fade_in_left as f32 / self.fade_len as f32;
+            self.fade_in_left -= 1;
+        }
+        let left = take - i;
+        if dry && left <= self.fade_len {
+            gain *= (left - 1) as f32 / self.fade_len as f32;
+        }
+        gain
+    }
+}
+
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut state: OutputState,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let mut scratch = Vec::new();
+    let shared = Arc::clone(&state.shared);
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+                let ts = info.timestamp();
+                if let Some(latency) = ts.playback.duration_since(&ts.callback) {
+                    let us = latency.as_micros().min(u32::MAX as u128) as u32;
+                    state.shared.latency_us.store(us, Ordering::Relaxed);
+                }
+                scratch.resize(data.len(), 0.0);
+                state.fill(&mut scratch);
+                for (out, &sample) in data.iter_mut().zip(&scratch) {
+                    *out = T::from_sample(sample);
+                }
+            },
+            move |e| {
+                eprintln!("audio output error: {}", e);
+                // The stream will not call back again; let waiters and `push` return.
+                if matches!(e, cpal::StreamError::DeviceNotAvailable) {
+                    shared.running.store(false, Ordering::Relaxed);
+                }
+            },
+            None,
+        )
+        .map_err(Error::audio)
+}
+
+/// Everything the input callback owns: downmix, resample to the recording rate, append.
+struct InputState {
+    channels: usize,
+    resampler: StreamResampler,
+    mono: Vec<f32>,
+    samples: Arc<Mutex<Vec<f32>>>,
+}
+
+fn build_input_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut state: InputState,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let channels = state.channels;
+                state.mono.clear();
+                state.mono.extend(data.chunks_exact(channels).map(|frame| {
+                    frame.iter().map(|&s| s.to_sample::<f32>()).sum::<f32>() / channels as f32
+                }));
+                let mut samples = state.samples.lock().unwrap();
+                state.resampler.process(&state.mono, &mut samples);
+            },
+            move |e| eprintln!("audio input error: {}", e),
+            None,
+        )
+        .map_err(Error::audio)
+}
+
+/// Record from the microphone (default, or picked by a `DeviceSelection`), convert to WAV bytes.
+#[derive(Clone)]
 pub struct AudioRecorder {
     sample_rate: u32,
-    available: bool,
+    /// Input device resolved at creation and used for every recording; `None` if there is none.
+    device: Option<cpal::Device>,
+    device_name: Option<String>,
 }
 
 impl AudioRecorder {
     pub fn new() -> Self {
-        let available = cpal::default_host()
-            .default_input_device()
-            .is_some();
+        Self::with_devices(&DeviceSelection::default())
+    }
+
+    /// Record from the input device picked by `devices`.
+    pub fn with_devices(devices: &DeviceSelection) -> Self {
+        let device = devices.input_device().ok();
         Self {
             sample_rate: RECORD_SAMPLE_RATE,
-            available,
+            device_name: device.as_ref().map(device_name),
+            device,
         }
     }
 
     pub fn available(&self) -> bool {
-        self.available
+        self.device_name.is_some()
+    }
+
+    /// Name of the input device, as resolved when the recorder was created.
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
     }
 
     /// Record until `stop` is signaled. Returns WAV file bytes (mono f32 → i16 for WAV).
-    pub fn record_blocking(&self, stop: impl Fn() -> bool) -> Result<Vec<u8>, String> {
-        if !self.available {
-            return Err("no microphone".into());
+    pub fn record_blocking(&self, stop: impl Fn() -> bool) -> Result<Vec<u8>> {
+        let recorded = self.capture(|_| stop())?;
+        if recorded.is_empty() {
+            return Ok(Vec::new());
         }
 
-        let device = cpal::default_host()
-            .default_input_device()
-            .ok_or("no input device")?;
+        samples_to_wav_bytes(&recorded, self.sample_rate)
+    }
 
-        let config = StreamConfig {
-            channels: CHANNELS,
-            sample_rate: cpal::SampleRate(self.sample_rate),
-            buffer_size: cpal::BufferSize::Default,
+    /// Record one utterance: stops after `vad.trailing_silence_ms` of silence following speech
+    /// (or when `stop` is signaled) and trims surrounding silence. Empty if no speech was heard.
+    pub fn record_until_silence(&self, vad: VadConfig, stop: impl Fn() -> bool) -> Result<Vec<u8>> {
+        let mut detector = Vad::new(vad, self.sample_rate);
+        let recorded = self.capture(|new| detector.push(new) || stop())?;
+        match detector.speech_range() {
+            Some(range) => samples_to_wav_bytes(&recorded[range], self.sample_rate),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Open the microphone and capture in the background until the `LiveCapture` is finished.
+    pub fn start(&self) -> Result<LiveCapture> {
+        let Some(device) = &self.device else {
+            return Err(Error::audio("no microphone"));
         };
+        let (config, format) = negotiate_config(
+            device.supported_input_configs().map_err(Error::audio)?.collect(),
+            device.default_input_config().ok(),
+            self.sample_rate,
+        )?;
 
-        let samples: Arc<std::sync::Mutex<Vec<f32>>> = Arc::new(std::sync::Mutex::new(Vec::new()));
-        let samples_clone = Arc::clone(&samples);
+        let samples: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::new()));
+        let input = InputState {
+            channels: config.channels as usize,
+            resampler: StreamResampler::new(config.sample_rate.0, self.sample_rate),
+            mono: Vec::new(),
+            samples: Arc::clone(&samples),
+        };
+        let stream = with_sample_type!(format, build_input_stream(device, &config, input))?;
 
-        let stream = device
-            .build_input_stream(
-                &config,
-                move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                    samples_clone.lock().unwrap().extend_from_slice(data);
-                },
-                move |e| eprintln!("audio input error: {}", e),
-                None,
-            )
-            .map_err(|e| e.to_string())?;
+        stream.play().map_err(Error::audio)?;
 
-        stream.play().map_err(|e| e.to_string())?;
+        Ok(LiveCapture {
+            stream,
+            samples,
+            seen: 0,
+            sample_rate: self.sample_rate,
+        })
+    }
 
-        while !stop() {
-            thread::sleep(std::time::Duration::from_millis(50));
+    /// Run the input stream, handing each newly captured block to `done` until it returns true.
+    fn capture(&self, mut done: impl FnMut(&[f32]) -> bool) -> Result<Vec<f32>> {
+        let mut live = self.start()?;
+        let mut block = Vec::new();
+        loop {
+            thread::sleep(RECORD_POLL_INTERVAL);
+            block.clear();
+            live.read_new(&mut block);
+            if done(&block) {
+                break;
+            }
         }
+        Ok(live.finish())
+    }
+}
 
-        drop(stream);
-        let recorded = samples.lock().unwrap().clone();
-        if recorded.is_empty() {
-            return Ok(Vec::new());
+/// Microphone capture running in the background. Everything captured is kept until `finish`.
+/// Not Send (cpal stream).
+pub struct LiveCapture {
+    stream: cpal::Stream,
+    samples: Arc<Mutex<Vec<f32>>>,
+    seen: usize,
+    sample_rate: u32,
+}
+
+impl LiveCapture {
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Append the samples captured since the previous call to `out`.
+    pub fn read_new(&mut self, out: &mut Vec<f32>) {
+        let captured = self.samples.lock().unwrap();
+        out.extend_from_slice(&captured[self.seen..]);
+        self.seen = captured.len();
+    }
+
+    /// Stop the microphone and return everything captured.
+    pub fn finish(self) -> Vec<f32> {
+        drop(self.stream);
+        std::mem::take(&mut *self.samples.lock().unwrap())
+    }
+}
+
+/// Energy / zero-crossing voice activity detection settings.
+#[derive(Clone, Copy, Debug)]
+pub struct VadConfig {
+    /// Silence after speech that ends the utterance.
+    pub trailing_silence_ms: u32,
+    /// How far above the tracked noise floor a frame must be to count as speech.
+    pub threshold_db: f32,
+    /// Continuous speech needed before an utterance starts (rejects clicks and bumps).
+    pub min_speech_ms: u32,
+    /// Audio kept before the first and after the last speech frame when trimming.
+    pub padding_ms: u32,
+    /// Hard limit on recording length, speech or not.
+    pub max_duration_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            trailing_silence_ms: 800,
+            threshold_db: 12.0,
+            min_speech_ms: 60,
+            padding_ms: 150,
+            max_duration_ms: 60_000,
         }
+    }
+}
 
-        samples_to_wav_bytes(&recorded, self.sample_rate)
+/// Streaming VAD over fixed 20 ms frames. A frame is speech when its energy clears the adaptive
+/// noise floor by `threshold_db`, or by half that with a high zero-crossing rate (fricatives).
+pub struct Vad {
+    config: VadConfig,
+    frame_len: usize,
+    pending: Vec<f32>,
+    frames: usize,
+    noise_floor_db: Option<f32>,
+    gate_db: Option<f32>,
+    speech_run: usize,
+    silence_run: usize,
+    first_speech: Option<usize>,
+    last_speech: Option<usize>,
+}
+
+impl Vad {
+    pub fn new(config: VadConfig, sample_rate: u32) -> Self {
+        let frame_len = (sample_rate * VAD_FRAME_MS / 1000) as usize;
+        Self {
+            config,
+            frame_len,
+            pending: Vec::with_capacity(frame_len),
+            frames: 0,
+            noise_floor_db: None,
+            gate_db: None,
+            speech_run: 0,
+            silence_run: 0,
+            first_speech: None,
+            last_speech: None,
+        }
+    }
+
+    /// Feed captured samples. Returns true once the utterance is over (speech followed by
+    /// trailing silence) or the maximum duration is reached.
+    pub fn push(&mut self, samples: &[f32]) -> bool {
+        let mut rest = samples;
+        while !rest.is_empty() {
+            let take = (self.frame_len - self.pending.len()).min(rest.len());
+            self.pending.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.pending.len() == self.frame_len {
+                let speech = self.classify_pending();
+                self.pending.clear();
+                self.update(speech);
+            }
+        }
+        self.is_done()
+    }
+
+    /// Treat frames quieter than `db` (dBFS) as non-speech without learning them as noise.
+    /// Used to ignore our own playback picked up by the microphone.
+    pub fn set_gate_db(&mut self, db: Option<f32>) {
+        self.gate_db = db;
+    }
+
+    pub fn speech_detected(&self) -> bool {
+        self.first_speech.is_some()
+    }
+
+    pub fn is_done(&self) -> bool {
+        let ms = |frames: usize| frames as u64 * VAD_FRAME_MS as u64;
+        ms(self.frames) >= self.config.max_duration_ms as u64
+            || (self.speech_detected()
+                && ms(self.silence_run) >= self.config.trailing_silence_ms as u64)
+    }
+
+    /// Sample range covering the detected speech plus padding, if any speech was heard.
+    pub fn speech_range(&self) -> Option<std::ops::Range<usize>> {
+        let (first, last) = (self.first_speech?, self.last_speech?);
+        let pad = (self.config.padding_ms / VAD_FRAME_MS) as usize;
+        let start = first.saturating_sub(pad) * self.frame_len;
+        let end = (last + 1 + pad).min(self.frames) * self.frame_len;
+        Some(start..end)
+    }
+
+    fn classify_pending(&mut self) -> bool {
+        let frame = &self.pending;
+        let energy = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+        let db = 10.0 * energy.max(1e-12).log10();
+        let crossings = frame
+            .windows(2)
+            .filter(|w| (w[0] >= 0.0) != (w[1] >= 0.0))
+            .count();
+        let zcr = crossings as f32 / frame.len() as f32;
+        if self.gate_db.is_some_and(|gate| db < gate) {
+            return false;
+        }
+
+        let floor = self.noise_floor_db.unwrap_or(db).max(VAD_MIN_FLOOR_DB);
+        let above = db - floor;
+        let speech = above > self.config.threshold_db
+            || (above > self.config.threshold_db / 2.0 && zcr > VAD_UNVOICED_ZCR);
+        if !speech {
+            // Drop quickly to quieter frames, creep up slowly so speech can't raise the floor.
+            let next = if db < floor { db } else { floor + 0.05 * (db - floor) };
+            self.noise_floor_db = Some(next.max(VAD_MIN_FLOOR_DB));
+        }
+        speech
+    }
+
+    fn update(&mut self, speech: bool) {
+        let index = self.frames;
+        self.frames += 1;
+        if speech {
+            self.speech_run += 1;
+            self.silence_run = 0;
+            let min_run = (self.config.min_speech_ms / VAD_FRAME_MS).max(1) as usize;
+            if self.speech_run >= min_run {
+                if self.first_speech.is_none() {
+                    self.first_speech = Some(index + 1 - self.speech_run);
+                }
+                self.last_speech = Some(index);
+            }
+        } else {
+            self.speech_run = 0;
+            self.silence_run += 1;
+        }
+    }
+}
+
+/// Listens to the microphone while the assistant is speaking and reports when the user starts
+/// talking over it. Frames that could be our own playback echoing back are ignored.
+pub struct BargeInMonitor {
+    live: LiveCapture,
+    vad: Vad,
+    playback: Option<PlaybackHandle>,
+    echo_db: f32,
+    block: Vec<f32>,
+    max_duration_ms: u32,
+}
+
+impl BargeInMonitor {
+    /// Start listening. `config` is the regular recording VAD; starting an interruption needs
+    /// a longer run of speech than starting a normal recording.
+    pub fn new(recorder: &AudioRecorder, config: VadConfig) -> Result<Self> {
+        let live = recorder.start()?;
+        let vad_config = VadConfig {
+            min_speech_ms: config.min_speech_ms.max(BARGE_IN_MIN_SPEECH_MS),
+            max_duration_ms: u32::MAX,
+            ..config
+        };
+        Ok(Self {
+            vad: Vad::new(vad_config, live.sample_rate()),
+            live,
+            playback: None,
+            echo_db: f32::NEG_INFINITY,
+            block: Vec::new(),
+            max_duration_ms: config.max_duration_ms,
+        })
+    }
+
+    /// Playback to treat as echo while it is audible.
+    pub fn watch_playback(&mut self, handle: PlaybackHandle) {
+        self.playback = Some(handle);
+    }
+
+    /// Process newly captured audio. Returns true once the user is speaking.
+    pub fn poll(&mut self) -> bool {
+        self.block.clear();
+        self.live.read_new(&mut self.block);
+        if self.block.is_empty() {
+            return self.vad.speech_detected();
+        }
+        let frame_len = self.live.sample_rate() * VAD_FRAME_MS / 1000;
+        let frames = self.block.len() as f32 / frame_len as f32;
+        let playing = self
+            .playback
+            .as_ref()
+            .map_or(f32::NEG_INFINITY, PlaybackHandle::output_level_db);
+        self.echo_db = (self.echo_db - ECHO_HOLD_DECAY_DB_PER_FRAME * frames).max(playing);
+        self.vad.set_gate_db(Some(self.echo_db - ECHO_ATTENUATION_DB));
+        self.vad.push(&self.block);
+        self.vad.speech_detected()
+    }
+
+    /// Resolves once the user starts speaking.
+    pub async fn speech_started(&mut self) {
+        while !self.poll() {
+            tokio::time::sleep(RECORD_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Keep recording until the interrupting utterance ends (or `stop` returns true); return
+    /// it trimmed, as WAV bytes.
+    pub async fn finish_utterance(mut self, stop: impl Fn() -> bool) -> Result<Vec<u8>> {
+        let limit = Duration::from_millis(self.max_duration_ms as u64);
+        let started = std::time::Instant::now();
+        while !self.vad.is_done() && !stop() && started.elapsed() < limit {
+            tokio::time::sleep(RECORD_POLL_INTERVAL).await;
+            self.poll();
+        }
+        let rate = self.live.sample_rate();
+        let range = self.vad.speech_range();
+        let samples = self.live.finish();
+        let speech = match range {
+            Some(r) => &samples[r.start.min(samples.len())..r.end.min(samples.len())],
+            None => &samples[..],
+        };
+        samples_to_wav_bytes(speech, rate)
+    }
+}
+
+impl Default for AudioRecorder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 /// Encode f32 samples (-1..1) to WAV bytes (16-bit PCM).
-pub fn samples_to_wav_bytes(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, String> {
+pub fn samples_to_wav_bytes(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
     let mut cursor = Cursor::new(Vec::<u8>::new());
     let spec = WavSpec {
         channels: CHANNELS,
@@ -196,13 +1164,620 @@ pub fn samples_to_wav_bytes(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>
         bits_per_sample: 16,
         sample_format: hound::SampleFormat::Int,
     };
-    let mut writer = WavWriter::new(&mut cursor, spec).map_err(|e| e.to_string())?;
+    let mut writer = WavWriter::new(&mut cursor, spec)?;
     for &s in samples {
         let clamped = s.clamp(-1.0, 1.0);
         let sample = (clamped * 32767.0) as i16;
-        writer.write_sample(sample).map_err(|e| e.to_string())?;
+        writer.write_sample(sample)?;
     }
-    writer.finalize().map_err(|e| e.to_string())?;
+    writer.finalize()?;
     Ok(cursor.into_inner())
 }
 
+/// Write f32 samples (mono) to a 32-bit float WAV file, bit-exact with the streamed audio.
+pub fn write_wav_f32(path: &Path, samples: &[f32], sample_rate: u32) -> Result<()> {
+    let spec = WavSpec {
+        channels: CHANNELS,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    for &s in samples {
+        writer.write_sample(s)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// File extensions `load_input_audio` can decode.
+#[cfg(feature = "opus")]
+pub const INPUT_AUDIO_EXTENSIONS: &[&str] = &["wav", "flac", "mp3", "ogg", "oga", "opus"];
+#[cfg(all(feature = "compressed-audio", not(feature = "opus")))]
+pub const INPUT_AUDIO_EXTENSIONS: &[&str] = &["wav", "flac", "mp3", "ogg", "oga"];
+#[cfg(not(feature = "compressed-audio"))]
+pub const INPUT_AUDIO_EXTENSIONS: &[&str] = &["wav"];
+
+/// Read an audio file and convert it to mono 16 kHz 16-bit WAV for the server.
+/// WAV is always supported; FLAC, MP3 and Ogg Vorbis need the `compressed-audio` feature and
+/// Ogg Opus the `opus` feature.
+pub fn load_input_audio(path: &Path) -> Result<Vec<u8>> {
+    let bytes = std::fs::read(path)?;
+    let result = if bytes.starts_with(b"RIFF") {
+        normalize_wav(&bytes)
+    } else {
+        decode_compressed(bytes, path.extension().and_then(|e| e.to_str()))
+    };
+    result.map_err(|e| match e {
+        Error::InvalidAudio(msg) => Error::InvalidAudio(format!("{}: {}", path.display(), msg)),
+        e => e,
+    })
+}
+
+#[cfg(feature = "compressed-audio")]
+fn decode_compressed(bytes: Vec<u8>, extension: Option<&str>) -> Result<Vec<u8>> {
+    let (mono, rate) = crate::decode::decode_to_mono(bytes, extension)?;
+    let resampled = resample(&mono, rate, RECORD_SAMPLE_RATE);
+    samples_to_wav_bytes(&resampled, RECORD_SAMPLE_RATE)
+}
+
+#[cfg(not(feature = "compressed-audio"))]
+fn decode_compressed(_bytes: Vec<u8>, _extension: Option<&str>) -> Result<Vec<u8>> {
+    Err(Error::InvalidAudio(
+        "not a WAV file (FLAC, MP3 and Ogg Vorbis need the `compressed-audio` feature)".into(),
+    ))
+}
+
+/// Validate WAV bytes and convert them to what the recorder produces: mono, 16 kHz, 16-bit PCM.
+/// Input already in that format is returned unchanged.
+pub fn normalize_wav(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = WavReader::new(Cursor::new(bytes)).map_err(|e| match e {
+        hound::Error::IoError(e) => Error::InvalidAudio(format!("truncated WAV ({})", e)),
+        e => Error::InvalidAudio(format!("not a supported WAV file ({})", e)),
+    })?;
+    let spec = reader.spec();
+    check_input_rate(spec.sample_rate)?;
+    if spec.channels == CHANNELS
+        && spec.sample_rate == RECORD_SAMPLE_RATE
+        && spec.bits_per_sample == 16
+        && spec.sample_format == hound::SampleFormat::Int
+    {
+        return Ok(bytes.to_vec());
+    }
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<std::result::Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|v| v as f32 * scale))
+                .collect::<std::result::Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels as usize;
+    if channels == 0 || interleaved.len() < channels {
+        return Err(Error::InvalidAudio("WAV file contains no audio".into()));
+    }
+    let mono: Vec<f32> = interleaved
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    let resampled = resample(&mono, spec.sample_rate, RECORD_SAMPLE_RATE);
+    samples_to_wav_bytes(&resampled, RECORD_SAMPLE_RATE)
+}
+
+/// Reject sample rates no real recording has before they reach the resampler.
+pub(crate) fn check_input_rate(rate: u32) -> Result<()> {
+    if (MIN_INPUT_SAMPLE_RATE..=MAX_INPUT_SAMPLE_RATE).contains(&rate) {
+        return Ok(());
+    }
+    Err(Error::InvalidAudio(format!(
+        "unsupported sample rate {} Hz (expected {}-{} Hz)",
+        rate, MIN_INPUT_SAMPLE_RATE, MAX_INPUT_SAMPLE_RATE
+    )))
+}
+
+/// Band-limited resampling of a whole mono buffer (Blackman-windowed sinc): runs
+/// `StreamResampler` over it without the streaming delay, so output `n` lines up with input
+/// `n * from_rate / to_rate`. Samples beyond either end count as silence.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let out_len = (samples.len() as f64 * to_rate as f64 / from_rate as f64).round() as usize;
+    let mut resampler = StreamResampler::new(from_rate, to_rate);
+    resampler.reset_centered();
+    let mut out = Vec::with_capacity(out_len + 1);
+    resampler.process(samples, &mut out);
+    // Flush: the last outputs need up to half a kernel of input past the end.
+    let tail = vec![0.0; resampler.taps];
+    resampler.process(&tail, &mut out);
+    out.resize(out_len, 0.0);
+    out
+}
+
+/// Blackman-windowed sinc at distance `d` (input samples) from the output position.
+fn sinc_tap(d: f64, cutoff: f64, half_width: f64) -> f64 {
+    if d.abs() >= half_width {
+        return 0.0;
+    }
+    let x = d * cutoff;
+    let sinc = if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+    };
+    let w = 0.5 * (d / half_width) + 0.5;
+    let blackman = 0.42 - 0.5 * (2.0 * std::f64::consts::PI * w).cos()
+        + 0.08 * (4.0 * std::f64::consts::PI * w).cos();
+    sinc * blackman
+}
+
+/// Streaming resampler for the audio callbacks (and, via `resample`, whole buffers): the
+/// windowed-sinc kernel is precomputed as a polyphase table (one row per output phase), so there
+/// is no trig or allocation per sample. Delays the signal by half the kernel width.
+struct StreamResampler {
+    /// Rates reduced by their gcd: `step` input samples per `phases` output samples.
+    step: u64,
+    phases: u64,
+    /// `phases` rows of `taps` weights, each row normalised to sum to 1.
+    table: Vec<f32>,
+    taps: usize,
+    /// Input samples from `history_start` on (absolute index; negative = leading zeros).
+    history: Vec<f32>,
+    history_start: i64,
+    /// Next output sample (absolute index).
+    next_out: u64,
+}
+
+impl StreamResampler {
+    fn new(from_rate: u32, to_rate: u32) -> Self {
+        let g = gcd(from_rate as u64, to_rate as u64).max(1);
+        let (step, phases) = (from_rate as u64 / g, to_rate as u64 / g);
+        let cutoff = (to_rate as f64 / from_rate as f64).min(1.0);
+        let half_width = SINC_ZERO_CROSSINGS / cutoff;
+        let reach = if step == phases { 0 } else { half_width.ceil() as usize };
+        let taps = 2 * reach + 1;
+        let mut table = Vec::with_capacity(phases as usize * taps);
+        for phase in 0..phases {
+            let frac = phase as f64 / phases as f64;
+            let row: Vec<f64> = (0..taps)
+                .map(|j| sinc_tap(frac + reach as f64 - j as f64, cutoff, half_width))
+                .collect();
+            let sum: f64 = row.iter().sum();
+            table.extend(row.iter().map(|w| (w / sum) as f32));
+        }
+        let mut resampler = Self {
+            step,
+            phases,
+            table,
+            taps,
+            history: Vec::new(),
+            history_start: 0,
+            next_out: 0,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    /// Forget buffered input, as if starting a new stream.
+    fn reset(&mut self) {
+        let reach = self.taps / 2;
+        self.history.clear();
+        self.history.resize(2 * reach, 0.0);
+        self.history_start = -(2 * reach as i64);
+        self.next_out = 0;
+    }
+
+    /// Like `reset`, but without the half-kernel delay: output `n` is centred on input
+    /// `n * step / phases`. Only for whole buffers, since the first outputs then need input
+    /// that arrives together with them.
+    fn reset_centered(&mut self) {
+        let reach = self.taps / 2;
+        self.reset();
+        self.history.truncate(reach);
+    }
+
+    /// Feed input samples; append every output sample that can now be computed.
+    fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        self.history.extend_from_slice(input);
+        let available_end = self.history_start + self.history.len() as i64;
+        loop {
+            let pos = self.next_out * self.step;
+            let (center, phase) = ((pos / self.phases) as i64, (pos % self.phases) as usize);
+            // Taps cover center - 2 * reach ..= center (kernel delayed by reach samples).
+            let first = center - (self.taps as i64 - 1);
+            if center >= available_end {
+                break;
+            }
+            let window = &self.history[(first - self.history_start) as usize..][..self.taps];
+            let row = &self.table[phase * self.taps..][..self.taps];
+            out.push(window.iter().zip(row).map(|(x, w)| x * w).sum());
+            self.next_out += 1;
+        }
+        // Keep only what the next output still needs.
+        let next_first = (self.next_out * self.step / self.phases) as i64 - (self.taps as i64 - 1);
+        let drop = (next_first - self.history_start).clamp(0, self.history.len() as i64) as usize;
+        self.history.drain(..drop);
+        self.history_start += drop as i64;
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, rate: u32, len: usize) -> Vec<f32> {
+        let step = 2.0 * std::f32::consts::PI * freq / rate as f32;
+        (0..len).map(|i| (i as f32 * step).sin() * 0.5).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// Direct evaluation of the kernel at output `n`, away from the buffer edges.
+    fn reference_sample(samples: &[f32], from_rate: u32, to_rate: u32, n: usize) -> f32 {
+        let ratio = to_rate as f64 / from_rate as f64;
+        let cutoff = ratio.min(1.0);
+        let half_width = SINC_ZERO_CROSSINGS / cutoff;
+        let t = n as f64 / ratio;
+        let (mut acc, mut weight) = (0.0, 0.0);
+        let first = (t - half_width).ceil() as usize;
+        for (k, &x) in samples.iter().enumerate().skip(first) {
+            let tap = sinc_tap(t - k as f64, cutoff, half_width);
+            if k as f64 > t + half_width {
+                break;
+            }
+            acc += x as f64 * tap;
+            weight += tap;
+        }
+        (acc / weight) as f32
+    }
+
+    #[test]
+    fn resample_matches_the_kernel() {
+        for (from, to) in [(48_000, 16_000), (44_100, 16_000), (8_000, 16_000), (22_050, 24_000)] {
+            let input = sine(440.0, from, from as usize / 10);
+            let out = resample(&input, from, to);
+            assert_eq!(out.len(), (input.len() as f64 * to as f64 / from as f64).round() as usize);
+            let margin = 200;
+            for n in (margin..out.len() - margin).step_by(37) {
+                let expected = reference_sample(&input, from, to, n);
+                assert!((out[n] - expected).abs() < 1e-4, "{}->{} at {}", from, to, n);
+            }
+        }
+    }
+
+    #[test]
+    fn downsampling_removes_content_above_nyquist() {
+        let passband = resample(&sine(1_000.0, 48_000, 48_000), 48_000, 16_000);
+        let stopband = resample(&sine(12_000.0, 48_000, 48_000), 48_000, 16_000);
+        let interior = |s: &[f32]| rms(&s[1_000..s.len() - 1_000]);
+        assert!((interior(&passband) - 0.5 / 2f32.sqrt()).abs() < 0.01);
+        assert!(interior(&stopband) < 1e-3);
+    }
+
+    #[test]
+    fn stream_resampler_is_independent_of_chunking() {
+        let input = sine(300.0, 44_100, 20_000);
+        let mut whole = StreamResampler::new(44_100, 24_000);
+        let mut expected = Vec::new();
+        whole.process(&input, &mut expected);
+
+        let mut chunked = StreamResampler::new(44_100, 24_000);
+        let mut out = Vec::new();
+        let mut rest = &input[..];
+        for size in [1, 2, 3, 500, 7, 4096].iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at((*size).min(rest.len()));
+            chunked.process(chunk, &mut out);
+            rest = tail;
+        }
+        assert_eq!(out, expected);
+    }
+
+    fn wav(spec: WavSpec, write: impl FnOnce(&mut WavWriter<&mut Cursor<Vec<u8>>>)) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut cursor, spec).unwrap();
+        write(&mut writer);
+        writer.finalize().unwrap();
+        cursor.into_inner()
+    }
+
+    fn int_spec(channels: u16, sample_rate: u32, bits_per_sample: u16) -> WavSpec {
+        WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample,
+            sample_format: hound::SampleFormat::Int,
+        }
+    }
+
+    fn decoded(bytes: &[u8]) -> (WavSpec, Vec<i16>) {
+        let mut reader = WavReader::new(Cursor::new(bytes)).unwrap();
+        let samples = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        (reader.spec(), samples)
+    }
+
+    #[test]
+    fn normalize_wav_passes_16k_mono_through() {
+        let bytes = wav(int_spec(1, 16_000, 16), |w| {
+            for i in 0..160 {
+                w.write_sample(i as i16 * 100).unwrap();
+            }
+        });
+        assert_eq!(normalize_wav(&bytes).unwrap(), bytes);
+    }
+
+    #[test]
+    fn normalize_wav_downmixes_stereo() {
+        let bytes = wav(int_spec(2, 16_000, 16), |w| {
+            for _ in 0..160 {
+                w.write_sample(16_000i16).unwrap();
+                w.write_sample(8_000i16).unwrap();
+            }
+        });
+        let (spec, samples) = decoded(&normalize_wav(&bytes).unwrap());
+        assert_eq!(spec, int_spec(1, 16_000, 16));
+        assert_eq!(samples.len(), 160);
+        assert!(samples.iter().all(|&s| (s - 12_000).abs() <= 1));
+    }
+
+    #[test]
+    fn normalize_wav_scales_24_and_8_bit() {
+        let half_scale_24 = wav(int_spec(1, 16_000, 24), |w| {
+            for _ in 0..160 {
+                w.write_sample(1i32 << 22).unwrap();
+            }
+        });
+        let half_scale_8 = wav(int_spec(1, 16_000, 8), |w| {
+            for _ in 0..160 {
+                w.write_sample(64i8).unwrap();
+            }
+        });
+        for bytes in [half_scale_24, half_scale_8] {
+            let (spec, samples) = decoded(&normalize_wav(&bytes).unwrap());
+            assert_eq!(spec, int_spec(1, 16_000, 16));
+            assert!(samples.iter().all(|&s| (s - 16_383).abs() <= 1), "{:?}", &samples[..4]);
+        }
+    }
+
+    #[test]
+    fn normalize_wav_resamples_to_16k() {
+        let bytes = wav(int_spec(1, 48_000, 16), |w| {
+            for s in sine(440.0, 48_000, 48_000) {
+                w.write_sample((s * 32_767.0) as i16).unwrap();
+            }
+        });
+        let (spec, samples) = decoded(&normalize_wav(&bytes).unwrap());
+        assert_eq!(spec.sample_rate, 16_000);
+        assert_eq!(samples.len(), 16_000);
+    }
+
+    #[test]
+    fn normalize_wav_rejects_absurd_sample_rates() {
+        let bytes = wav(int_spec(1, 16_000, 16), |w| {
+            for s in sine(440.0, 16_000, 1_600) {
+                w.write_sample((s * 32_767.0) as i16).unwrap();
+            }
+        });
+        // Patch the fmt chunk's sample rate and byte rate (bytes 24 and 28 of a canonical
+        // header), as a hostile file would.
+        let with_rate = |rate: u32| {
+            let mut patched = bytes.clone();
+            patched[24..28].copy_from_slice(&rate.to_le_bytes());
+            patched[28..32].copy_from_slice(&rate.wrapping_mul(2).to_le_bytes());
+            normalize_wav(&patched)
+        };
+        for rate in [0, 999, 384_001, 50_000_000] {
+            match with_rate(rate) {
+                Err(Error::InvalidAudio(msg)) => assert!(msg.contains("sample rate"), "{}", msg),
+                other => panic!("rate {} gave {:?}", rate, other.map(|b| b.len())),
+            }
+        }
+        assert!(with_rate(384_000).is_ok());
+    }
+
+    #[test]
+    fn normalize_wav_rejects_non_wav() {
+        assert!(matches!(normalize_wav(b"RIFF...."), Err(Error::InvalidAudio(_))));
+        assert!(matches!(normalize_wav(b"hello"), Err(Error::InvalidAudio(_))));
+    }
+
+    /// 20 ms at the recording rate.
+    const VAD_FRAME: usize = 320;
+
+    fn vad() -> Vad {
+        let config = VadConfig {
+            trailing_silence_ms: 200,
+            threshold_db: 12.0,
+            min_speech_ms: 60,
+            padding_ms: 100,
+            max_duration_ms: 2_000,
+        };
+        Vad::new(config, RECORD_SAMPLE_RATE)
+    }
+
+    /// Deterministic low-level noise, about -65 dBFS.
+    fn hiss(frames: usize) -> Vec<f32> {
+        let mut state = 0x2545_f491u32;
+        (0..frames * VAD_FRAME)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1 << 24) as f32 * 0.002 - 0.001
+            })
+            .collect()
+    }
+
+    fn voice(frames: usize) -> Vec<f32> {
+        sine(220.0, RECORD_SAMPLE_RATE, frames * VAD_FRAME)
+    }
+
+    /// Feed whole frames, each split across two pushes. Returns how many frames it took for
+    /// the VAD to report the utterance over.
+    fn feed(vad: &mut Vad, samples: &[f32]) -> Option<usize> {
+        for (i, frame) in samples.chunks(VAD_FRAME).enumerate() {
+            let (head, tail) = frame.split_at(100);
+            assert!(!vad.push(head));
+            if vad.push(tail) {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn vad_ends_after_trailing_silence_and_pads_the_speech() {
+        let mut vad = vad();
+        assert_eq!(feed(&mut vad, &hiss(25)), None);
+        assert!(!vad.speech_detected());
+        assert_eq!(vad.speech_range(), None);
+
+        assert_eq!(feed(&mut vad, &voice(20)), None);
+        assert!(vad.speech_detected());
+
+        // 200 ms of silence ends it; the range is frames 25..45 plus 100 ms on each side.
+        assert_eq!(feed(&mut vad, &hiss(30)), Some(10));
+        assert_eq!(vad.speech_range(), Some(20 * VAD_FRAME..50 * VAD_FRAME));
+    }
+
+    #[test]
+    fn vad_ignores_bursts_shorter_than_min_speech() {
+        let mut vad = vad();
+        let mut audio = hiss(10);
+        audio.extend(voice(2));
+        audio.extend(hiss(20));
+        assert_eq!(feed(&mut vad, &audio), None);
+        assert!(!vad.speech_detected());
+        assert_eq!(vad.speech_range(), None);
+    }
+
+    #[test]
+    fn vad_stops_at_max_duration() {
+        let mut silent = vad();
+        assert_eq!(feed(&mut silent, &hiss(150)), Some(100));
+        assert_eq!(silent.speech_range(), None);
+
+        // Speech running into the limit: the trailing pad is cut at the end of the recording.
+        let mut talking = vad();
+        let mut audio = hiss(10);
+        audio.extend(voice(140));
+        assert_eq!(feed(&mut talking, &audio), Some(100));
+        assert_eq!(talking.speech_range(), Some(5 * VAD_FRAME..100 * VAD_FRAME));
+    }
+
+    #[test]
+    fn vad_leading_pad_stops_at_the_first_frame() {
+        let mut vad = vad();
+        let mut audio = hiss(2);
+        audio.extend(voice(10));
+        audio.extend(hiss(10));
+        assert_eq!(feed(&mut vad, &audio), Some(22));
+        assert_eq!(vad.speech_range(), Some(0..17 * VAD_FRAME));
+    }
+
+    /// At 1 kHz, milliseconds and samples coincide: 100 sample pre-roll, 5 sample fades.
+    fn jitter(buffer_ms: u32) -> JitterBuffer {
+        let config = PlaybackConfig {
+            buffer_ms,
+            preroll_ms: 100,
+        };
+        JitterBuffer::new(config, 1_000)
+    }
+
+    #[test]
+    fn jitter_waits_for_preroll_unless_the_stream_ended() {
+        let mut jb = jitter(4_000);
+        assert!(!jb.may_play(99, true));
+        assert!(jb.may_play(100, true));
+        // Once playing, a shrinking queue does not stop it; only running dry does.
+        assert!(jb.may_play(1, true));
+
+        let mut ended = jitter(4_000);
+        assert!(!ended.may_play(0, false));
+        assert!(ended.may_play(10, false));
+    }
+
+    #[test]
+    fn jitter_preroll_grows_on_underruns_up_to_the_cap() {
+        let mut jb = jitter(4_000);
+        assert!(jb.may_play(100, true));
+        assert!(jb.ran_dry(true));
+        assert_eq!(jb.preroll, 150);
+        assert!(!jb.may_play(149, true));
+        assert!(jb.may_play(150, true));
+
+        let grown: Vec<usize> = (0..6)
+            .map(|_| {
+                jb.ran_dry(true);
+                jb.preroll
+            })
+            .collect();
+        assert_eq!(grown, [225, 337, 505, 757, 1_000, 1_000]);
+
+        // Running dry after the stream ended is not an underrun.
+        assert!(!jb.ran_dry(false));
+        assert_eq!(jb.preroll, 1_000);
+
+        // The cap is also held to half the ring.
+        let mut small = jitter(400);
+        for _ in 0..5 {
+            small.ran_dry(true);
+        }
+        assert_eq!(small.preroll, 200);
+    }
+
+    #[test]
+    fn jitter_preroll_relaxes_after_smooth_responses() {
+        let mut jb = jitter(4_000);
+        jb.ran_dry(true);
+        jb.track_stream(true);
+        jb.track_stream(false);
+        // That response had an underrun: keep the larger pre-roll.
+        assert_eq!(jb.preroll, 150);
+        jb.track_stream(true);
+        jb.track_stream(false);
+        assert_eq!(jb.preroll, 135);
+        for _ in 0..20 {
+            jb.track_stream(true);
+            jb.track_stream(false);
+        }
+        assert_eq!(jb.preroll, 100);
+    }
+
+    fn assert_gains(jb: &mut JitterBuffer, dry: bool, expected: &[f32]) {
+        let take = expected.len();
+        let gains: Vec<f32> = (0..take).map(|i| jb.gain(i, take, dry)).collect();
+        let close = gains.iter().zip(expected).all(|(g, e)| (g - e).abs() < 1e-6);
+        assert!(close, "gains {:?}, expected {:?}", gains, expected);
+    }
+
+    #[test]
+    fn jitter_fades_in_on_start_and_out_when_dry() {
+        let mut jb = jitter(4_000);
+        assert!(jb.may_play(100, true));
+        assert_gains(&mut jb, false, &[0.0, 0.2, 0.4, 0.6, 0.8, 1.0, 1.0]);
+
+        // The last sample before running dry is silent, so the output ends without a click.
+        assert_gains(&mut jb, true, &[1.0, 1.0, 0.8, 0.6, 0.4, 0.2, 0.0]);
+
+        // Resuming after the underrun fades in again.
+        jb.ran_dry(true);
+        assert!(jb.may_play(150, true));
+        assert_gains(&mut jb, false, &[0.0, 0.2, 0.4, 0.6, 0.8, 1.0]);
+    }
+}
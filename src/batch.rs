@@ -0,0 +1,456 @@
+//! Non-interactive batch subcommands: no REPL, no audio devices, results written to disk.
+
+use futures_util::StreamExt;
+use liquid_audio_chat::api::{process_stream, FinishReason, StreamStats};
+use liquid_audio_chat::audio::{
+    load_input_audio, samples_to_wav_bytes, INPUT_AUDIO_EXTENSIONS, PLAYBACK_SAMPLE_RATE,
+};
+use liquid_audio_chat::{Error, LiquidAudioClient};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args)]
+pub struct TtsArgs {
+    /// Text to synthesize; omit or pass "-" to read one prompt per line from stdin
+    text: Option<String>,
+    /// Read one prompt per line from this file (blank lines are skipped)
+    #[arg(long, short, conflicts_with = "text")]
+    file: Option<PathBuf>,
+    /// Directory for the numbered WAV files (24 kHz 16-bit PCM) and manifest.json
+    #[arg(long, short, default_value = ".")]
+    out_dir: PathBuf,
+}
+
+#[derive(Serialize)]
+struct TtsManifest<'a> {
+    base_url: &'a str,
+    sample_rate: u32,
+    items: Vec<TtsItem>,
+}
+
+#[derive(Serialize)]
+struct TtsItem {
+    index: usize,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audio_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<StreamStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn read_prompts(args: &TtsArgs) -> io::Result<Vec<String>> {
+    let raw = match (&args.text, &args.file) {
+        (Some(text), _) if text != "-" => return Ok(vec![text.clone()]),
+        (_, Some(path)) => std::fs::read_to_string(path)?,
+        _ => io::read_to_string(io::stdin())?,
+    };
+    Ok(raw
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Why a response did not end with a normal `stop`, if it did not.
+fn incomplete(stats: &StreamStats) -> Option<String> {
+    match &stats.finish_reason {
+        Some(FinishReason::Stop) => None,
+        None => Some("stream ended before the server finished".into()),
+        Some(FinishReason::Length) => Some("truncated at --max-tokens".into()),
+        Some(FinishReason::ContentFilter) => Some("stopped by the content filter".into()),
+        Some(reason) => Some(format!("finished with reason '{}'", reason.as_str())),
+    }
+}
+
+async fn synthesize(
+    client: &LiquidAudioClient,
+    text: &str,
+) -> Result<(StreamStats, Vec<f32>), Error> {
+    let res = client.tts(text).await?;
+    let mut audio = Vec::new();
+    let (_, stats) =
+        process_stream(res, |_| {}, |samples| audio.extend_from_slice(samples)).await?;
+    Ok((stats, audio))
+}
+
+/// Synthesize every prompt to `NNNN.wav` and write `manifest.json`. Returns false if any failed.
+pub async fn run_tts(client: &LiquidAudioClient, args: TtsArgs) -> Result<bool, Error> {
+    let prompts = read_prompts(&args)?;
+    std::fs::create_dir_all(&args.out_dir)?;
+    let mut items = Vec::with_capacity(prompts.len());
+    let mut all_ok = true;
+
+    for (i, text) in prompts.into_iter().enumerate() {
+        let index = i + 1;
+        let mut item = TtsItem {
+            index,
+            text,
+            file: None,
+            audio_secs: None,
+            stats: None,
+            error: None,
+        };
+        let result = match synthesize(client, &item.text).await {
+            // A cut-off prompt must not ship as if it were complete.
+            Ok((stats, _)) if incomplete(&stats).is_some() => {
+                let reason = incomplete(&stats).unwrap_or_default();
+                item.stats = Some(stats);
+                Err(format!("incomplete audio: {}", reason))
+            }
+            Ok((_, audio)) if audio.is_empty() => Err("response contained no audio".to_string()),
+            Ok((stats, audio)) => {
+                let name = format!("{:04}.wav", index);
+                samples_to_wav_bytes(&audio, PLAYBACK_SAMPLE_RATE)
+                    .and_then(|wav| Ok(std::fs::write(args.out_dir.join(&name), wav)?))
+                    .map(|()| {
+                        (
+                            name,
+                            audio.len() as f64 / PLAYBACK_SAMPLE_RATE as f64,
+                            stats,
+                        )
+                    })
+                    .map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok((name, audio_secs, stats)) => {
+                eprintln!(
+                    "[{}] {} ttft {:.3}s total {:.3}s audio {:.1}s",
+                    index,
+                    name,
+                    stats.ttft_secs.unwrap_or(0.0),
+                    stats.total_secs,
+                    audio_secs
+                );
+                item.file = Some(name);
+                item.audio_secs = Some(audio_secs);
+                item.stats = Some(stats);
+            }
+            Err(e) => {
+                eprintln!("[{}] error: {}", index, e);
+                item.error = Some(e);
+                all_ok = false;
+            }
+        }
+        items.push(item);
+    }
+
+    let manifest = TtsManifest {
+        base_url: client.base_url(),
+        sample_rate: PLAYBACK_SAMPLE_RATE,
+        items,
+    };
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| Error::Io(e.into()))?;
+    std::fs::write(args.out_dir.join("manifest.json"), json)?;
+    Ok(all_ok)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TranscriptFormat {
+    /// `file: transcript  [ttft | total]`
+    Text,
+    /// One JSON object per file
+    Jsonl,
+    /// Header row, then file,ttft_secs,total_secs,finish_reason,transcript,error
+    Csv,
+}
+
+#[derive(clap::Args)]
+pub struct AsrArgs {
+    /// Audio files, directories (searched recursively) or quoted glob patterns
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Number of requests in flight at once
+    #[arg(long, short = 'j', default_value_t = 4)]
+    concurrency: usize,
+    #[arg(long, value_enum, default_value_t = TranscriptFormat::Text)]
+    format: TranscriptFormat,
+    /// Write transcripts to this file instead of stdout
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct AsrRecord {
+    file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transcript: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttft_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_secs: Option<f64>,
+    /// Absent if the stream ended without one (or the request failed).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finish_reason: Option<FinishReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn has_audio_extension(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+        INPUT_AUDIO_EXTENSIONS
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(e))
+    })
+}
+
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk_dir(&path, files)?;
+        } else if has_audio_extension(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Expand inputs in order: existing files as-is, directories recursively, anything else as a glob.
+fn collect_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            walk_dir(path, &mut files)?;
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        } else {
+            let pattern = glob::glob(input)
+                .map_err(|e| Error::InvalidRequest(format!("bad pattern '{}': {}", input, e)))?;
+            let before = files.len();
+            for entry in pattern {
+                let entry = entry.map_err(|e| Error::Io(e.into_error()))?;
+                if entry.is_file() {
+                    files.push(entry);
+                }
+            }
+            if files.len() == before {
+                return Err(Error::InvalidRequest(format!("no files match '{}'", input)));
+            }
+        }
+    }
+    Ok(files)
+}
+
+async fn transcribe(
+    client: &LiquidAudioClient,
+    path: &Path,
+) -> Result<(String, StreamStats), Error> {
+    let path_buf = path.to_path_buf();
+    let wav = tokio::task::spawn_blocking(move || load_input_audio(&path_buf))
+        .await
+        .map_err(|e| Error::Io(io::Error::other(e)))??;
+    let res = client.asr(&wav).await?;
+    let (text, stats) = process_stream(res, |_| {}, |_| {}).await?;
+    Ok((text.trim().to_string(), stats))
+}
+
+fn csv_field(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// The record for one file; a response that did not finish normally gets an `error` too.
+fn asr_record(file: String, result: Result<(String, StreamStats), Error>) -> AsrRecord {
+    match result {
+        Ok((transcript, stats)) => AsrRecord {
+            file,
+            transcript: Some(transcript),
+            ttft_secs: stats.ttft_secs,
+            total_secs: Some(stats.total_secs),
+            // A disconnected or truncated transcript is not a result to compare against.
+            error: incomplete(&stats).map(|reason| format!("incomplete: {}", reason)),
+            finish_reason: stats.finish_reason,
+        },
+        Err(e) => AsrRecord {
+            file,
+            transcript: None,
+            ttft_secs: None,
+            total_secs: None,
+            finish_reason: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+fn write_record(out: &mut dyn Write, format: TranscriptFormat, rec: &AsrRecord) -> io::Result<()> {
+    match format {
+        TranscriptFormat::Text => match (&rec.transcript, &rec.error) {
+            (Some(t), Some(e)) => writeln!(out, "{}: ERROR {} (partial: {})", rec.file, e, t),
+            (None, Some(e)) => writeln!(out, "{}: ERROR {}", rec.file, e),
+            (t, None) => writeln!(
+                out,
+                "{}: {}  [ttft {:.3}s | total {:.3}s]",
+                rec.file,
+                t.as_deref().unwrap_or(""),
+                rec.ttft_secs.unwrap_or(0.0),
+                rec.total_secs.unwrap_or(0.0)
+            ),
+        },
+        TranscriptFormat::Jsonl => {
+            serde_json::to_writer(&mut *out, rec)?;
+            writeln!(out)
+        }
+        TranscriptFormat::Csv => writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            csv_field(&rec.file),
+            rec.ttft_secs
+                .map(|t| format!("{:.3}", t))
+                .unwrap_or_default(),
+            rec.total_secs
+                .map(|t| format!("{:.3}", t))
+                .unwrap_or_default(),
+            rec.finish_reason
+                .as_ref()
+                .map(|r| csv_field(r.as_str()))
+                .unwrap_or_default(),
+            csv_field(rec.transcript.as_deref().unwrap_or("")),
+            csv_field(rec.error.as_deref().unwrap_or(""))
+        ),
+    }
+}
+
+/// Transcribe every input file, `concurrency` at a time, writing results in input order.
+/// Returns false if any file failed.
+pub async fn run_asr(client: &LiquidAudioClient, args: AsrArgs) -> Result<bool, Error> {
+    let files = collect_inputs(&args.inputs)?;
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    if args.format == TranscriptFormat::Csv {
+        writeln!(
+            out,
+            "file,ttft_secs,total_secs,finish_reason,transcript,error"
+        )?;
+    }
+
+    let mut results = futures_util::stream::iter(files.iter())
+        .map(|path| async move { (path, transcribe(client, path).await) })
+        .buffered(args.concurrency.max(1));
+    let mut failed = 0usize;
+    while let Some((path, result)) = results.next().await {
+        let rec = asr_record(path.display().to_string(), result);
+        failed += usize::from(rec.error.is_some());
+        write_record(&mut *out, args.format, &rec)?;
+        out.flush()?;
+    }
+    eprintln!("{} files, {} failed", files.len(), failed);
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh empty directory under the system temp dir, unique per test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lac-batch-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn finished(reason: Option<&str>) -> StreamStats {
+        StreamStats {
+            ttft_secs: Some(0.1),
+            total_secs: 0.5,
+            completed: reason.is_some(),
+            finish_reason: reason.map(FinishReason::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert!(matches!(
+            csv_field("plain text"),
+            Cow::Borrowed("plain text")
+        ));
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\r"), "\"cr\r\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn inputs_keep_argument_order_and_directories_are_sorted() {
+        let dir = temp_dir("inputs");
+        std::fs::create_dir_all(dir.join("d/sub")).unwrap();
+        for file in [
+            "b.wav",
+            "a.wav",
+            "d/z.wav",
+            "d/sub/y.WAV",
+            "d/notes.txt",
+            "g1.wav",
+        ] {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        let arg = |p: &str| dir.join(p).to_string_lossy().into_owned();
+        let files = collect_inputs(&[arg("b.wav"), arg("d"), arg("g*.wav"), arg("a.wav")]).unwrap();
+        let expected: Vec<PathBuf> = ["b.wav", "d/sub/y.WAV", "d/z.wav", "g1.wav", "a.wav"]
+            .iter()
+            .map(|p| dir.join(p))
+            .collect();
+        assert_eq!(files, expected);
+
+        match collect_inputs(&[arg("a.wav"), arg("missing*.wav")]) {
+            Err(Error::InvalidRequest(msg)) => {
+                assert!(msg.starts_with("no files match"), "{}", msg)
+            }
+            other => panic!("expected no-match error, got {:?}", other.map(|f| f.len())),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unfinished_transcripts_are_errors() {
+        let rec = asr_record(
+            "ok.wav".into(),
+            Ok(("hello".into(), finished(Some("stop")))),
+        );
+        assert_eq!(rec.error, None);
+        assert_eq!(rec.transcript.as_deref(), Some("hello"));
+
+        for (reason, expected) in [
+            (None, "incomplete: stream ended before the server finished"),
+            (Some("length"), "incomplete: truncated at --max-tokens"),
+            (
+                Some("content_filter"),
+                "incomplete: stopped by the content filter",
+            ),
+            (
+                Some("tool_calls"),
+                "incomplete: finished with reason 'tool_calls'",
+            ),
+        ] {
+            let rec = asr_record("cut.wav".into(), Ok(("partial".into(), finished(reason))));
+            assert_eq!(rec.error.as_deref(), Some(expected));
+            // The partial transcript is kept alongside the error.
+            assert_eq!(rec.transcript.as_deref(), Some("partial"));
+            assert_eq!(rec.finish_reason, reason.map(FinishReason::from));
+        }
+
+        let rec = asr_record("bad.wav".into(), Err(Error::InvalidAudio("empty".into())));
+        assert!(rec.transcript.is_none() && rec.total_secs.is_none());
+        assert!(rec.error.is_some());
+    }
+}
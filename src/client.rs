@@ -0,0 +1,139 @@
+//! High-level client that owns the HTTP connection pool, server URL and request defaults.
+
+use crate::api::{stream_chat, stream_single_shot, ChatMessage, Mode};
+use crate::error::{Error, Result};
+use reqwest::Client;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const DEFAULT_BASE_URL: &str = "http://127.0.0.1:8080/v1";
+pub const DEFAULT_MAX_TOKENS: u32 = 512;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Reusable LFM2.5-Audio client. Cheap to clone (the inner `reqwest::Client` is shared).
+#[derive(Clone)]
+pub struct LiquidAudioClient {
+    http: Client,
+    base_url: String,
+    max_tokens: u32,
+    include_usage: bool,
+    /// The server refused `stream_options`; shared by clones so it is only tried once.
+    usage_rejected: Arc<AtomicBool>,
+}
+
+impl LiquidAudioClient {
+    /// Client with its own connection pool and a 300 s request timeout.
+    pub fn new(base_url: impl Into<String>) -> Result<Self> {
+        let http = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(Error::Transport)?;
+        Ok(Self::with_http_client(http, base_url))
+    }
+
+    /// Client reusing an existing `reqwest::Client` (shared pool, custom TLS, proxies...).
+    pub fn with_http_client(http: Client, base_url: impl Into<String>) -> Self {
+        Self {
+            http,
+            base_url: base_url.into(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            include_usage: true,
+            usage_rejected: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Ask for token usage at the end of each stream (`stream_options.include_usage`, on by
+    /// default). A server that rejects the option with a 4xx naming it gets the request again
+    /// without it, and is not asked again; turning it off skips that first round trip.
+    pub fn include_usage(mut self, include_usage: bool) -> Self {
+        self.include_usage = include_usage;
+        self
+    }
+
+    /// Send a request built by `send(include_usage)`, retrying once without usage if the server
+    /// rejects `stream_options`.
+    async fn send_with_usage<F, Fut>(&self, send: F) -> Result<reqwest::Response>
+    where
+        F: Fn(bool) -> Fut,
+        Fut: Future<Output = Result<reqwest::Response>>,
+    {
+        let include_usage = self.include_usage && !self.usage_rejected.load(Ordering::Relaxed);
+        match send(include_usage).await {
+            Err(Error::Http { status, body })
+                if include_usage && status.is_client_error() && body.contains("stream_options") =>
+            {
+                self.usage_rejected.store(true, Ordering::Relaxed);
+                send(false).await
+            }
+            res => res,
+        }
+    }
+
+    #[inline]
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    #[inline]
+    pub fn http(&self) -> &Client {
+        &self.http
+    }
+
+    /// Single-shot transcription of WAV bytes. Feed the response to `process_stream`.
+    pub async fn asr(&self, wav_data: &[u8]) -> Result<reqwest::Response> {
+        self.send_with_usage(|include_usage| {
+            stream_single_shot(
+                &self.http,
+                &self.base_url,
+                Mode::Asr,
+                None,
+                Some(wav_data),
+                self.max_tokens,
+                include_usage,
+            )
+        })
+        .await
+    }
+
+    /// Single-shot synthesis of `text`. Audio arrives as 24 kHz f32 chunks in the stream.
+    pub async fn tts(&self, text: &str) -> Result<reqwest::Response> {
+        self.send_with_usage(|include_usage| {
+            stream_single_shot(
+                &self.http,
+                &self.base_url,
+                Mode::Tts,
+                Some(text),
+                None,
+                self.max_tokens,
+                include_usage,
+            )
+        })
+        .await
+    }
+
+    /// Interleaved chat turn. `reset_context` asks the server to drop its previous context.
+    pub async fn chat(
+        &self,
+        messages: &[ChatMessage],
+        reset_context: bool,
+    ) -> Result<reqwest::Response> {
+        self.send_with_usage(|include_usage| {
+            stream_chat(
+                &self.http,
+                &self.base_url,
+                messages,
+                self.max_tokens,
+                reset_context,
+                include_usage,
+            )
+        })
+        .await
+    }
+}
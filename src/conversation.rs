@@ -0,0 +1,177 @@
+//! Chat history of an interleaved conversation, kept client-side so it can be resent or saved.
+
+use crate::api::{create_audio_message, ChatMessage, Mode};
+use crate::audio::{samples_to_wav_bytes, PLAYBACK_SAMPLE_RATE};
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// One conversation turn. Assistant audio is kept for saving but never sent back to the model.
+#[derive(Clone)]
+pub struct Turn {
+    pub role: Role,
+    pub text: Option<String>,
+    /// WAV file bytes: user input audio, or assistant output at 24 kHz.
+    pub audio: Option<Vec<u8>>,
+}
+
+impl Turn {
+    fn append_messages(&self, out: &mut Vec<ChatMessage>) {
+        if let Some(t) = &self.text {
+            if !t.is_empty() {
+                out.push(ChatMessage::text(self.role.as_str(), t.as_str()));
+            }
+        }
+        if self.role == Role::User {
+            if let Some(wav) = &self.audio {
+                out.push(create_audio_message(wav));
+            }
+        }
+    }
+}
+
+/// Turns plus the request messages derived from them (cached so each turn is encoded once).
+#[derive(Clone)]
+pub struct Conversation {
+    turns: Vec<Turn>,
+    messages: Vec<ChatMessage>,
+    /// Turns from here on were added or replaced since `mark_saved`.
+    unsaved_from: usize,
+}
+
+impl Conversation {
+    /// Empty conversation starting with the interleaved-mode system prompt.
+    pub fn new() -> Self {
+        Self::with_system_prompt(Mode::Interleaved.system_prompt())
+    }
+
+    pub fn with_system_prompt(system: &str) -> Self {
+        Self::from_turns(vec![Turn {
+            role: Role::System,
+            text: Some(system.into()),
+            audio: None,
+        }])
+    }
+
+    pub fn from_turns(turns: Vec<Turn>) -> Self {
+        let mut conv = Self {
+            turns,
+            messages: Vec::new(),
+            unsaved_from: 0,
+        };
+        conv.rebuild_messages();
+        conv
+    }
+
+    /// Append a user turn: optional text, then optional WAV audio.
+    pub fn push_user(&mut self, text: Option<&str>, wav_data: Option<&[u8]>) {
+        self.push(Turn {
+            role: Role::User,
+            text: text.map(str::to_string),
+            audio: wav_data.map(<[u8]>::to_vec),
+        });
+    }
+
+    /// Append the assistant's reply: text and the streamed 24 kHz f32 audio.
+    pub fn push_assistant(&mut self, text: &str, samples: &[f32]) -> Result<()> {
+        if text.is_empty() && samples.is_empty() {
+            return Ok(());
+        }
+        let audio = if samples.is_empty() {
+            None
+        } else {
+            Some(samples_to_wav_bytes(samples, PLAYBACK_SAMPLE_RATE)?)
+        };
+        self.push(Turn {
+            role: Role::Assistant,
+            text: Some(text.to_string()),
+            audio,
+        });
+        Ok(())
+    }
+
+    fn push(&mut self, turn: Turn) {
+        turn.append_messages(&mut self.messages);
+        self.turns.push(turn);
+    }
+
+    fn rebuild_messages(&mut self) {
+        self.messages.clear();
+        for turn in &self.turns {
+            turn.append_messages(&mut self.messages);
+        }
+    }
+
+    #[inline]
+    pub fn turns(&self) -> &[Turn] {
+        &self.turns
+    }
+
+    /// Request messages for the whole conversation.
+    #[inline]
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    /// Number of turns (not messages).
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.turns.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    /// Drop turns after `len` (e.g. to roll back a user turn whose request failed).
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.turns.len() {
+            self.turns.truncate(len);
+            self.unsaved_from = self.unsaved_from.min(len);
+            self.rebuild_messages();
+        }
+    }
+
+    /// Drop everything except the system prompt.
+    pub fn clear(&mut self) {
+        self.turns.retain(|t| t.role == Role::System);
+        self.unsaved_from = self.unsaved_from.min(self.turns.len());
+        self.rebuild_messages();
+    }
+
+    /// Index of the first turn added or replaced since the last `mark_saved` (0 for a new or
+    /// loaded conversation).
+    #[inline]
+    pub fn unsaved_from(&self) -> usize {
+        self.unsaved_from
+    }
+
+    /// Record that every current turn has been saved.
+    pub fn mark_saved(&mut self) {
+        self.unsaved_from = self.turns.len();
+    }
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
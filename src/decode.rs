@@ -0,0 +1,382 @@
+//! Decoding of compressed input audio. FLAC, MP3 and Ogg Vorbis are decoded in pure Rust via
+//! symphonia; Ogg Opus is demuxed by symphonia and decoded by libopus behind the `opus` feature.
+
+use crate::error::{Error, Result};
+use std::io::Cursor;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, Packet};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Decode the first audio track to mono f32. Returns the samples and their sample rate.
+pub(crate) fn decode_to_mono(bytes: Vec<u8>, extension: Option<&str>) -> Result<(Vec<f32>, u32)> {
+    let mss = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = extension {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            // Gapless: mark encoder delay and padding (Opus end padding, MP3 LAME info) for trimming.
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| Error::InvalidAudio(format!("unrecognised audio format ({})", e)))?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| Error::InvalidAudio("file has no audio track".into()))?;
+    let track_id = track.id;
+    if track.codec_params.codec == CODEC_TYPE_OPUS {
+        #[cfg(feature = "opus")]
+        return opus::decode(format.as_mut(), track_id);
+        #[cfg(not(feature = "opus"))]
+        return Err(Error::InvalidAudio(
+            "Opus needs the `opus` feature (links libopus); convert to FLAC or WAV instead".into(),
+        ));
+    }
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| Error::InvalidAudio("unknown sample rate".into()))?;
+    crate::audio::check_input_rate(sample_rate)?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| Error::InvalidAudio(format!("unsupported codec ({})", e)))?;
+
+    let mut mono = Vec::new();
+    let mut buf: Option<SampleBuffer<f32>> = None;
+    while let Some(packet) = next_packet(format.as_mut(), track_id)? {
+        let decoded = match decoder.decode(&packet) {
+            Ok(d) => d,
+            // Corrupt frame: skip it like a player would.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(Error::InvalidAudio(format!("decode failed ({})", e))),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let frames = decoded.capacity() as u64;
+        let sb = match &mut buf {
+            Some(sb) if sb.capacity() >= frames as usize * channels => sb,
+            _ => buf.insert(SampleBuffer::new(frames, spec)),
+        };
+        sb.copy_interleaved_ref(decoded);
+        mono.extend(
+            sb.samples()
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+    }
+    finish(mono, sample_rate)
+}
+
+/// The next packet of `track_id`, or `None` at the end of the stream.
+fn next_packet(format: &mut dyn FormatReader, track_id: u32) -> Result<Option<Packet>> {
+    loop {
+        match format.next_packet() {
+            Ok(p) if p.track_id() == track_id => return Ok(Some(p)),
+            Ok(_) => continue,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Ok(None)
+            }
+            Err(SymphoniaError::ResetRequired) => return Ok(None),
+            Err(e) => return Err(Error::InvalidAudio(format!("demux failed ({})", e))),
+        }
+    }
+}
+
+fn finish(mono: Vec<f32>, sample_rate: u32) -> Result<(Vec<f32>, u32)> {
+    if mono.is_empty() {
+        return Err(Error::InvalidAudio("file contains no audio".into()));
+    }
+    Ok((mono, sample_rate))
+}
+
+/// Ogg Opus via libopus. symphonia has no Opus decoder, but its Ogg demuxer splits the packets,
+/// keeps the OpusHead header as extra data and marks the end padding as a trim count. The
+/// pre-skip it leaves in, so it is read from OpusHead and dropped here.
+#[cfg(feature = "opus")]
+mod opus {
+    use super::{finish, next_packet};
+    use crate::error::{Error, Result};
+    use audiopus::coder::Decoder;
+    use audiopus::{Channels, SampleRate};
+    use symphonia::core::formats::FormatReader;
+
+    /// Opus always decodes to 48 kHz, whatever rate the encoder was fed.
+    const OPUS_RATE: u32 = 48_000;
+    /// Longest Opus packet: 120 ms at 48 kHz.
+    const MAX_FRAME: usize = 5760;
+
+    pub(super) fn decode(format: &mut dyn FormatReader, track_id: u32) -> Result<(Vec<f32>, u32)> {
+        let params = &format
+            .tracks()
+            .iter()
+            .find(|t| t.id == track_id)
+            .expect("track id comes from this reader")
+            .codec_params;
+        // Mapping family 0 (mono/stereo) only; surround needs the multistream decoder.
+        let channels = match params.channels.map(|c| c.count()) {
+            Some(1) => Channels::Mono,
+            Some(2) => Channels::Stereo,
+            _ => {
+                return Err(Error::InvalidAudio(
+                    "only mono and stereo Opus are supported".into(),
+                ))
+            }
+        };
+        let count = channels as usize;
+        // OpusHead: magic (8), version (1), channel count (1), pre-skip (u16 LE).
+        let mut skip = params
+            .extra_data
+            .as_deref()
+            .filter(|head| head.len() >= 12)
+            .map_or(0, |head| u16::from_le_bytes([head[10], head[11]]) as usize);
+        let mut decoder = Decoder::new(SampleRate::Hz48000, channels)
+            .map_err(|e| Error::InvalidAudio(format!("libopus init failed ({})", e)))?;
+
+        let mut mono = Vec::new();
+        let mut pcm = vec![0f32; MAX_FRAME * count];
+        while let Some(packet) = next_packet(format, track_id)? {
+            let input = match packet.buf().try_into() {
+                Ok(p) => p,
+                // Empty packet: nothing to decode.
+                Err(_) => continue,
+            };
+            let output = (&mut pcm[..])
+                .try_into()
+                .expect("output buffer is not empty");
+            let frames = match decoder.decode_float(Some(input), output, false) {
+                Ok(n) => n,
+                // Corrupt frame: skip it like a player would.
+                Err(_) => continue,
+            };
+            let pre_skip = skip.min(frames);
+            skip -= pre_skip;
+            let start = (packet.trim_start as usize).max(pre_skip).min(frames);
+            let end = frames.saturating_sub(packet.trim_end as usize).max(start);
+            mono.extend(
+                pcm[start * count..end * count]
+                    .chunks_exact(count)
+                    .map(|frame| frame.iter().sum::<f32>() / count as f32),
+            );
+        }
+        finish(mono, OPUS_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_to_mono;
+    use crate::error::Error;
+
+    /// FLAC CRC-8 (frame header): polynomial 0x07, zero initial value.
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |mut crc, &b| {
+            crc ^= b;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 {
+                    (crc << 1) ^ 0x07
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+
+    /// FLAC CRC-16 (whole frame): polynomial 0x8005, zero initial value.
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |mut crc, &b| {
+            crc ^= (b as u16) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x8005
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+
+    /// 44.1 kHz 16-bit stereo FLAC in 256-sample blocks of verbatim (uncompressed) subframes.
+    /// Everything stays byte-aligned, so no bit writer is needed.
+    fn flac(left: &[i16], right: &[i16]) -> Vec<u8> {
+        const BLOCK: usize = 256;
+        let total = left.len() as u64;
+        let mut out = b"fLaC".to_vec();
+        // Last metadata block, STREAMINFO, 34 bytes.
+        out.extend_from_slice(&[0x80, 0, 0, 34]);
+        out.extend_from_slice(&(BLOCK as u16).to_be_bytes());
+        out.extend_from_slice(&(BLOCK as u16).to_be_bytes());
+        out.extend_from_slice(&[0; 6]);
+        // Sample rate (20 bits), channels - 1 (3), bits per sample - 1 (5), total samples (36).
+        let packed = (44_100u64 << 44) | (1 << 41) | (15 << 36) | total;
+        out.extend_from_slice(&packed.to_be_bytes());
+        out.extend_from_slice(&[0; 16]);
+
+        for (n, (l, r)) in left.chunks(BLOCK).zip(right.chunks(BLOCK)).enumerate() {
+            let start = out.len();
+            // Sync, fixed block size; block size in an 8-bit field after the header, rate from
+            // STREAMINFO; independent stereo, 16 bits; frame number (< 128, one byte).
+            out.extend_from_slice(&[0xff, 0xf8, 0x60, 0x18, n as u8, (l.len() - 1) as u8]);
+            out.push(crc8(&out[start..]));
+            for channel in [l, r] {
+                // Verbatim subframe, no wasted bits.
+                out.push(0x02);
+                out.extend(channel.iter().flat_map(|s| s.to_be_bytes()));
+            }
+            let crc = crc16(&out[start..]);
+            out.extend_from_slice(&crc.to_be_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn decodes_flac_and_downmixes_stereo() {
+        // Not a multiple of the block size: the short last block reuses the sample buffer.
+        let len = 1000;
+        let left: Vec<i16> = (0..len)
+            .map(|i| ((i as f32 * 0.05).sin() * 16_000.0) as i16)
+            .collect();
+        let right: Vec<i16> = (0..len).map(|i| (i as i16 - 500) * 20).collect();
+
+        let (mono, rate) = decode_to_mono(flac(&left, &right), Some("flac")).unwrap();
+
+        assert_eq!(rate, 44_100);
+        assert_eq!(mono.len(), len);
+        for (i, m) in mono.iter().enumerate() {
+            let expected = (left[i] as f32 + right[i] as f32) / 2.0 / 32_768.0;
+            assert!(
+                (m - expected).abs() < 1e-6,
+                "sample {}: {} vs {}",
+                i,
+                m,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn flac_rate_is_checked() {
+        let mut file = flac(&[0; 16], &[0; 16]);
+        // Rewrite the STREAMINFO rate (first 20 bits of byte 18 on) to 500 Hz.
+        let packed = u64::from_be_bytes(file[18..26].try_into().unwrap());
+        let packed = (packed & ((1 << 44) - 1)) | (500 << 44);
+        file[18..26].copy_from_slice(&packed.to_be_bytes());
+        match decode_to_mono(file, Some("flac")) {
+            Err(Error::InvalidAudio(msg)) => assert!(msg.contains("sample rate"), "{}", msg),
+            other => panic!("500 Hz was accepted: {:?}", other.map(|(_, rate)| rate)),
+        }
+    }
+
+    #[cfg(feature = "opus")]
+    mod opus {
+        use super::super::decode_to_mono;
+        use audiopus::coder::Encoder;
+        use audiopus::{Application, Channels, SampleRate};
+
+        const FRAME: usize = 960;
+
+        /// Ogg page CRC: polynomial 0x04c11db7, no reflection, zero initial value.
+        fn crc(data: &[u8]) -> u32 {
+            data.iter().fold(0u32, |mut crc, &b| {
+                crc ^= (b as u32) << 24;
+                for _ in 0..8 {
+                    crc = if crc & 0x8000_0000 != 0 {
+                        (crc << 1) ^ 0x04c1_1db7
+                    } else {
+                        crc << 1
+                    };
+                }
+                crc
+            })
+        }
+
+        /// One Ogg page holding a single packet (under 255 * 255 bytes).
+        fn page(out: &mut Vec<u8>, flags: u8, granule: u64, seq: u32, packet: &[u8]) {
+            let start = out.len();
+            out.extend_from_slice(b"OggS\0");
+            out.push(flags);
+            out.extend_from_slice(&granule.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            out.extend_from_slice(&seq.to_le_bytes());
+            out.extend_from_slice(&[0; 4]);
+            let mut lacing = vec![255u8; packet.len() / 255];
+            lacing.push((packet.len() % 255) as u8);
+            out.push(lacing.len() as u8);
+            out.extend_from_slice(&lacing);
+            out.extend_from_slice(packet);
+            let sum = crc(&out[start..]);
+            out[start + 22..start + 26].copy_from_slice(&sum.to_le_bytes());
+        }
+
+        /// Encode mono 48 kHz samples as an Ogg Opus file.
+        fn ogg_opus(samples: &[f32]) -> Vec<u8> {
+            let encoder =
+                Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Audio).unwrap();
+            let pre_skip = encoder.lookahead().unwrap() as usize;
+
+            let mut out = Vec::new();
+            let mut head = b"OpusHead\x01\x01".to_vec();
+            head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+            head.extend_from_slice(&48_000u32.to_le_bytes());
+            head.extend_from_slice(&[0, 0, 0]);
+            page(&mut out, 0x02, 0, 0, &head);
+            page(&mut out, 0, 0, 1, b"OpusTags\0\0\0\0\0\0\0\0");
+
+            // Pad so the encoder's lookahead is flushed and the input fills whole frames.
+            let total = samples.len() + pre_skip;
+            let mut pcm: Vec<i16> = samples.iter().map(|s| (s * 32767.0) as i16).collect();
+            pcm.resize(total.div_ceil(FRAME) * FRAME, 0);
+            let frames = pcm.len() / FRAME;
+            let mut packet = [0u8; 4000];
+            for (i, frame) in pcm.chunks_exact(FRAME).enumerate() {
+                let len = encoder.encode(frame, &mut packet).unwrap();
+                let last = i + 1 == frames;
+                let granule = if last { total } else { (i + 1) * FRAME };
+                let flags = if last { 0x04 } else { 0 };
+                page(
+                    &mut out,
+                    flags,
+                    granule as u64,
+                    i as u32 + 2,
+                    &packet[..len],
+                );
+            }
+            out
+        }
+
+        #[test]
+        fn decodes_ogg_opus_without_pre_skip_or_padding() {
+            let step = 2.0 * std::f32::consts::PI * 440.0 / 48_000.0;
+            let samples: Vec<f32> = (0..24_000).map(|i| (i as f32 * step).sin() * 0.5).collect();
+
+            let (mono, rate) = decode_to_mono(ogg_opus(&samples), Some("opus")).unwrap();
+
+            assert_eq!(rate, 48_000);
+            assert_eq!(mono.len(), samples.len());
+            let middle = &mono[4_800..19_200];
+            let rms = (middle.iter().map(|s| s * s).sum::<f32>() / middle.len() as f32).sqrt();
+            assert!((rms - 0.5 / 2f32.sqrt()).abs() < 0.02, "rms {}", rms);
+            // Lossy, but aligned: the decoded signal tracks the input sample for sample.
+            let err = middle
+                .iter()
+                .zip(&samples[4_800..19_200])
+                .map(|(a, b)| (a - b).abs());
+            assert!(err.fold(0f32, f32::max) < 0.1);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Error type shared by the HTTP client, stream decoder and audio I/O.
+
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Server answered with a non-success status; `body` is the response text.
+    Http {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    /// Request never got a response (connection refused, DNS, TLS, timeout).
+    Transport(reqwest::Error),
+    /// Response body failed after streaming started.
+    Disconnected(reqwest::Error),
+    /// Server reported an error inside the response stream (after a success status).
+    Server {
+        message: String,
+        code: Option<String>,
+    },
+    /// Stream payload could not be decoded (bad base64 audio, etc.).
+    Decode(String),
+    /// Request rejected client-side before anything was sent.
+    InvalidRequest(String),
+    /// Input audio is not a usable audio file (detected before any request is sent).
+    InvalidAudio(String),
+    /// cpal device lookup, stream build or playback failure.
+    AudioDevice(String),
+    /// Session file is malformed, of an unsupported version, or references missing audio.
+    Session(String),
+    /// WAV encoding or parsing failure.
+    Wav(hound::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http { status, body } if body.is_empty() => write!(f, "server returned {}", status),
+            Error::Http { status, body } => write!(f, "server returned {}: {}", status, body),
+            Error::Transport(e) => write!(f, "request failed: {}", e),
+            Error::Disconnected(e) => write!(f, "stream interrupted: {}", e),
+            Error::Server { message, code: Some(code) } => {
+                write!(f, "server error ({}): {}", code, message)
+            }
+            Error::Server { message, code: None } => write!(f, "server error: {}", message),
+            Error::Decode(msg) => write!(f, "could not decode stream: {}", msg),
+            Error::InvalidRequest(msg) => write!(f, "invalid request: {}", msg),
+            Error::InvalidAudio(msg) => write!(f, "invalid audio: {}", msg),
+            Error::AudioDevice(msg) => write!(f, "audio device: {}", msg),
+            Error::Session(msg) => write!(f, "session: {}", msg),
+            Error::Wav(e) => write!(f, "wav: {}", e),
+            Error::Io(e) => write!(f, "io: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) | Error::Disconnected(e) => Some(e),
+            Error::Wav(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<hound::Error> for Error {
+    fn from(e: hound::Error) -> Self {
+        Error::Wav(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl Error {
+    pub(crate) fn audio(e: impl fmt::Display) -> Self {
+        Error::AudioDevice(e.to_string())
+    }
+}
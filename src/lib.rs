@@ -0,0 +1,16 @@
+//! Low-latency LFM2.5-Audio client library: streaming chat API and cpal audio I/O.
+
+pub mod api;
+pub mod audio;
+mod client;
+pub mod conversation;
+#[cfg(feature = "compressed-audio")]
+mod decode;
+pub mod error;
+mod ring;
+pub mod session;
+pub mod sse;
+
+pub use client::{LiquidAudioClient, DEFAULT_BASE_URL, DEFAULT_MAX_TOKENS};
+pub use conversation::{Conversation, Role, Turn};
+pub use error::{Error, Result};
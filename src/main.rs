@@ -1,20 +1,529 @@
 //! Low-latency, low-resource LFM2.5-Audio chat client (Rust).
 
-mod api;
-mod audio;
+mod batch;
 
-use api::{
-    stream_chat, stream_single_shot, process_stream, ChatMessage, StreamStats, MessageContent,
+use clap::{Parser, Subcommand};
+use liquid_audio_chat::api::{
+    process_stream, process_stream_until, AudioSink, FinishReason, Mode, StreamStats, TokenUsage,
+};
+use liquid_audio_chat::audio::{
+    available_hosts, host_name, list_devices, load_input_audio, write_wav_f32, AudioPlayer,
+    AudioRecorder, BargeInMonitor, DeviceSelection, PlaybackConfig, PlaybackHandle, PlaybackSink,
+    PlaybackStats, VadConfig, PLAYBACK_SAMPLE_RATE, RECORD_SAMPLE_RATE,
+};
+use liquid_audio_chat::session::AudioStorage;
+use liquid_audio_chat::{
+    Conversation, Error, LiquidAudioClient, DEFAULT_BASE_URL, DEFAULT_MAX_TOKENS,
 };
-use audio::{AudioPlayer, AudioRecorder, PlaybackHandle};
-use clap::Parser;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Only utterances this short are transcribed to check for the voice-mode stop word.
+const STOP_WORD_MAX_SECS: f64 = 1.5;
+
+/// Sent as the next user turn when --auto-continue resumes a reply cut off by --max-tokens.
+const CONTINUE_PROMPT: &str = "Continue.";
+
+/// Set while the voice loop runs; Ctrl-C then ends the loop instead of the program.
+static VOICE_ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Set while a response streams or plays; Ctrl-C then cancels it instead of exiting.
+static RESPONDING: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Resolves once Ctrl-C is pressed (while `VOICE_ACTIVE` or `RESPONDING`).
+async fn interrupted() {
+    while !INTERRUPTED.load(Ordering::Relaxed) {
+        tokio::time::sleep(INTERRUPT_POLL_INTERVAL).await;
+    }
+}
+
+/// Resolves once the user talks over the reply; never without a monitor.
+async fn barged_in(monitor: Option<&mut BargeInMonitor>) {
+    match monitor {
+        Some(monitor) => monitor.speech_started().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Interleaved-mode context. The conversation is always recorded (for /save); it is resent in
+/// full when `client_history` is set or once after a /load (`replay`).
+struct ChatState {
+    is_first_message: bool,
+    client_history: bool,
+    replay: bool,
+    /// Keep the assistant's audio in `history`; it is never sent, so only when audio is saved.
+    keep_reply_audio: bool,
+    history: Conversation,
+}
+
+impl ChatState {
+    fn new(client_history: bool, keep_reply_audio: bool) -> Self {
+        Self {
+            is_first_message: true,
+            client_history,
+            replay: false,
+            keep_reply_audio,
+            history: Conversation::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.is_first_message = true;
+        self.replay = false;
+        self.history.clear();
+    }
+
+    fn restore(&mut self, history: Conversation) {
+        self.history = history;
+        self.is_first_message = false;
+        self.replay = true;
+    }
+
+    /// Undo a user turn whose request failed. Server context is now unknown, so resend it all.
+    fn rollback(&mut self, history_len: usize) {
+        self.history.truncate(history_len);
+        self.replay = true;
+    }
+}
+
+fn load_session(path: &Path, chat: &mut ChatState) -> Result<(), Error> {
+    let history = Conversation::load(path)?;
+    println!("Loaded {} turns from {}", history.len(), path.display());
+    chat.restore(history);
+    Ok(())
+}
+
+/// Next free `NNNN-<mode>.wav` name in `dir`, so reruns never overwrite earlier responses.
+fn next_audio_path(dir: &Path, counter: &mut u32, mode: Mode) -> PathBuf {
+    loop {
+        *counter += 1;
+        let path = dir.join(format!("{:04}-{}.wav", counter, mode));
+        if !path.exists() {
+            return path;
+        }
+    }
+}
+
+/// Feeds response audio to the speakers (waiting when the playback buffer is full) and keeps
+/// a copy for /saveaudio and the history.
+struct ReplySink<'a> {
+    playback: Option<PlaybackSink>,
+    reply_audio: &'a mut Vec<f32>,
+}
+
+impl AudioSink for ReplySink<'_> {
+    fn write(&mut self, samples: &[f32]) {
+        if let Some(p) = &mut self.playback {
+            p.write(samples);
+        }
+        self.reply_audio.extend_from_slice(samples);
+    }
+
+    async fn ready(&mut self) {
+        if let Some(p) = &mut self.playback {
+            p.ready().await;
+        }
+    }
+}
+
+/// Token usage summed over the session, for /usage.
+#[derive(Default)]
+struct SessionUsage {
+    tokens: TokenUsage,
+    /// Responses that reported usage, and those that did not (server without usage support,
+    /// or cancelled).
+    reported: u32,
+    unreported: u32,
+}
+
+impl SessionUsage {
+    fn add(&mut self, stats: &StreamStats) {
+        match stats.usage {
+            Some(usage) => {
+                self.tokens += usage;
+                self.reported += 1;
+            }
+            None => self.unreported += 1,
+        }
+    }
+
+    fn print(&self) {
+        if self.reported == 0 {
+            println!("No token usage reported yet");
+            return;
+        }
+        let audio = |n: Option<u64>| n.map(|n| format!(" ({} audio)", n)).unwrap_or_default();
+        let t = &self.tokens;
+        println!("Prompt:     {}{}", t.prompt_tokens, audio(t.prompt_audio_tokens));
+        println!("Completion: {}{}", t.completion_tokens, audio(t.completion_audio_tokens));
+        println!("Total:      {}", t.total_tokens());
+        if self.unreported > 0 {
+            println!("Responses:  {} ({} without usage)", self.reported, self.unreported);
+        } else {
+            println!("Responses:  {}", self.reported);
+        }
+    }
+}
+
+/// REPL state shared by the prompt loop and the hands-free voice loop.
+struct Repl {
+    client: LiquidAudioClient,
+    args: Args,
+    recorder: AudioRecorder,
+    devices: DeviceSelection,
+    /// Opened once and kept for the whole session; `None` with --no-audio-playback.
+    player: Option<AudioPlayer>,
+    vad_config: VadConfig,
+    chat: ChatState,
+    /// Audio of the last response (for /saveaudio).
+    reply_audio: Vec<f32>,
+    audio_counter: u32,
+    usage: SessionUsage,
+}
+
+impl Repl {
+    /// A player stops for good when its device goes away or stalls; open a fresh one before
+    /// the next reply. If that fails, carry on without playback and say so.
+    fn reopen_player_if_stopped(&mut self) {
+        if self.player.as_ref().is_none_or(AudioPlayer::is_running) {
+            return;
+        }
+        self.player = None;
+        match AudioPlayer::open(&self.devices, playback_config(&self.args)) {
+            Ok(player) => {
+                println!("[audio output reopened: {}]", player.device_name());
+                self.player = Some(player);
+            }
+            Err(e) => println!("[audio output unavailable, continuing without playback: {}]", e),
+        }
+    }
+
+    /// Send one turn, stream the reply to stdout and the speakers, wait for playback to finish,
+    /// then report and save it. With `barge_in`, the user speaking cuts the reply off, whether
+    /// still streaming or playing. Ctrl-C cancels the reply the same way.
+    async fn respond(
+        &mut self,
+        mode: Mode,
+        text_input: Option<&str>,
+        wav_data: Option<&[u8]>,
+        mut barge_in: Option<&mut BargeInMonitor>,
+    ) -> Option<StreamStats> {
+        self.reopen_player_if_stopped();
+        let playback_handle = self.player.as_ref().map(AudioPlayer::handle);
+        if let Some(h) = &playback_handle {
+            h.take_stats();
+        }
+        if let (Some(monitor), Some(h)) = (barge_in.as_deref_mut(), &playback_handle) {
+            monitor.watch_playback(h.clone());
+        }
+
+        println!();
+
+        if !VOICE_ACTIVE.load(Ordering::Relaxed) {
+            INTERRUPTED.store(false, Ordering::Relaxed);
+        }
+        RESPONDING.store(true, Ordering::Relaxed);
+        self.reply_audio.clear();
+        let (mut text_input, mut wav_data) = (text_input, wav_data);
+        let mut continuations = 0;
+        let result = loop {
+            let cancel = async {
+                tokio::select! {
+                    _ = barged_in(barge_in.as_deref_mut()) => {}
+                    _ = interrupted() => {}
+                }
+            };
+            let result = self
+                .run_request(mode, text_input, wav_data, playback_handle.clone(), cancel)
+                .await;
+            if let Ok(stats) = &result {
+                self.usage.add(stats);
+            }
+            let truncated =
+                matches!(&result, Ok(stats) if stats.finish_reason == Some(FinishReason::Length));
+            if !truncated || mode != Mode::Interleaved || continuations >= self.args.auto_continue {
+                break result;
+            }
+            if let Ok(stats) = &result {
+                // One stats line per segment; playback health goes on the last one.
+                print_stats(stats, None, self.args.debug);
+            }
+            continuations += 1;
+            println!("[continuing ({}/{})]", continuations, self.args.auto_continue);
+            (text_input, wav_data) = (Some(CONTINUE_PROMPT), None);
+        };
+
+        if let Some(h) = &playback_handle {
+            // Whatever outlasted the stream is still queued; let it finish unless cut off.
+            let mut cut_off = result.is_err() || matches!(&result, Ok(stats) if stats.cancelled);
+            if !cut_off {
+                tokio::select! {
+                    _ = h.wait_until_empty() => {}
+                    _ = barged_in(barge_in) => {
+                        cut_off = true;
+                        println!("[interrupted]");
+                    }
+                    _ = interrupted() => cut_off = true,
+                }
+            }
+            if cut_off {
+                // Drop queued audio so the speakers go quiet at once, not after the buffer.
+                h.flush();
+            }
+        }
+        RESPONDING.store(false, Ordering::Relaxed);
 
-const SYSTEM_INTERLEAVED: &str = "Respond with interleaved text and audio.";
+        match result {
+            Ok(stats) => {
+                let playback = playback_handle.as_ref().map(PlaybackHandle::take_stats);
+                print_stats(&stats, playback, self.args.debug);
+                self.save_outputs(mode);
+                Some(stats)
+            }
+            Err(e) => {
+                print_error(&e, self.client.base_url());
+                None
+            }
+        }
+    }
+
+    /// Hands-free interleaved chat: listen (VAD), send the utterance, play the reply, repeat
+    /// until Ctrl-C or, with --voice-stop-word, the stop word.
+    async fn voice_loop(&mut self) {
+        if !self.recorder.available() {
+            println!("[No microphone available. Voice mode needs one.]");
+            return;
+        }
+        let stop_word = self.args.voice_stop_word.as_deref().map(|w| w.trim().to_lowercase());
+        let stop_word = stop_word.filter(|w| !w.is_empty());
+        match &stop_word {
+            Some(word) => println!("Voice mode: say \"{}\" or press Ctrl-C to stop", word),
+            None => println!("Voice mode: press Ctrl-C to stop"),
+        }
+        INTERRUPTED.store(false, Ordering::Relaxed);
+        VOICE_ACTIVE.store(true, Ordering::Relaxed);
+        // An utterance that interrupted the previous reply becomes the next turn.
+        let mut pending: Option<Vec<u8>> = None;
+        loop {
+            let wav = match pending.take() {
+                Some(wav) => wav,
+                None => {
+                    println!("\nListening...");
+                    // Recording blocks until the utterance ends; keep it off the async workers.
+                    let recorded = tokio::task::block_in_place(|| {
+                        self.recorder.record_until_silence(self.vad_config, || {
+                            INTERRUPTED.load(Ordering::Relaxed)
+                        })
+                    });
+                    if INTERRUPTED.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    match recorded {
+                        Ok(wav) if wav.is_empty() => continue,
+                        Ok(wav) => wav,
+                        Err(e) => {
+                            print_error(&e, self.client.base_url());
+                            break;
+                        }
+                    }
+                }
+            };
+            if let Some(word) = &stop_word {
+                if self.is_stop_word(&wav, word).await {
+                    break;
+                }
+            }
+            let mut monitor = if self.args.no_barge_in {
+                None
+            } else {
+                match BargeInMonitor::new(&self.recorder, self.vad_config) {
+                    Ok(m) => Some(m),
+                    Err(e) => {
+                        print_error(&e, self.client.base_url());
+                        None
+                    }
+                }
+            };
+            self.respond(Mode::Interleaved, None, Some(&wav), monitor.as_mut())
+                .await;
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                break;
+            }
+            if let Some(mut monitor) = monitor {
+                if monitor.poll() {
+                    println!("\nListening...");
+                    match monitor
+                        .finish_utterance(|| INTERRUPTED.load(Ordering::Relaxed))
+                        .await
+                    {
+                        Ok(wav) => pending = Some(wav),
+                        Err(e) => print_error(&e, self.client.base_url()),
+                    }
+                    if INTERRUPTED.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+            }
+        }
+        VOICE_ACTIVE.store(false, Ordering::Relaxed);
+        println!("\nVoice mode ended");
+    }
+
+    /// Transcribe a short utterance and compare it with the stop word. ASR requests reset the
+    /// server context, so the conversation is replayed on the next turn.
+    async fn is_stop_word(&mut self, wav: &[u8], stop_word: &str) -> bool {
+        let secs = wav.len().saturating_sub(44) as f64 / 2.0 / RECORD_SAMPLE_RATE as f64;
+        if secs > STOP_WORD_MAX_SECS {
+            return false;
+        }
+        self.chat.replay = true;
+        let transcript = match self.client.asr(wav).await {
+            Ok(res) => match process_stream(res, |_| {}, |_| {}).await {
+                Ok((text, stats)) => {
+                    self.usage.add(&stats);
+                    text
+                }
+                Err(_) => return false,
+            },
+            Err(_) => return false,
+        };
+        let normalized: String = transcript
+            .chars()
+            .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+            .collect::<String>()
+            .to_lowercase();
+        normalized.trim() == stop_word
+    }
+
+    /// Run one request, stopping early if `cancel` resolves first, whether still waiting for the
+    /// response headers or streaming. A cut-off interleaved reply is kept in the history as far
+    /// as it got.
+    async fn run_request(
+        &mut self,
+        mode: Mode,
+        text_input: Option<&str>,
+        wav_data: Option<&[u8]>,
+        playback_handle: Option<PlaybackHandle>,
+        cancel: impl std::future::Future<Output = ()>,
+    ) -> Result<StreamStats, Error> {
+        let (client, chat, reply_audio) = (&self.client, &mut self.chat, &mut self.reply_audio);
+        // With --auto-continue, `reply_audio` already holds the earlier parts of the reply.
+        let audio_start = reply_audio.len();
+        let history_len = chat.history.len();
+        tokio::pin!(cancel);
+        let sent_at = Instant::now();
+        let send = async {
+            match mode {
+                Mode::Asr => client.asr(wav_data.unwrap_or_default()).await,
+                Mode::Tts => client.tts(text_input.unwrap_or_default()).await,
+                Mode::Interleaved => {
+                    let sent = chat.history.messages().len();
+                    chat.history.push_user(text_input, wav_data);
+                    let resend = chat.client_history || chat.replay;
+                    let reset = chat.is_first_message || resend;
+                    let messages = if reset {
+                        chat.history.messages()
+                    } else {
+                        &chat.history.messages()[sent..]
+                    };
+                    chat.is_first_message = false;
+                    chat.replay = false;
+                    client.chat(messages, reset).await
+                }
+            }
+        };
+        let res = tokio::select! {
+            res = send => Some(res),
+            _ = &mut cancel => None,
+        };
+        let res = match res {
+            Some(Ok(r)) => r,
+            None => {
+                // Cancelled before the server answered. It may still have taken the turn, so
+                // drop it and resend the history next time.
+                if mode == Mode::Interleaved {
+                    chat.rollback(history_len);
+                }
+                println!("\n[interrupted]");
+                return Ok(StreamStats {
+                    total_secs: sent_at.elapsed().as_secs_f64(),
+                    cancelled: true,
+                    ..Default::default()
+                });
+            }
+            Some(Err(e)) => {
+                if mode == Mode::Interleaved {
+                    chat.rollback(history_len);
+                }
+                return Err(e);
+            }
+        };
+        let mut stdout = std::io::stdout();
+        let on_text = |s: &str| {
+            let _ = stdout.write_all(s.as_bytes());
+            let _ = stdout.flush();
+        };
+        let on_audio = ReplySink {
+            playback: playback_handle.as_ref().map(PlaybackHandle::sink),
+            reply_audio: &mut *reply_audio,
+        };
+
+        let (full_text, stats) = match process_stream_until(res, cancel, on_text, on_audio).await {
+            Ok(r) => r,
+            Err(e) => {
+                // End the partial reply's line before the error is printed.
+                println!();
+                if mode == Mode::Interleaved {
+                    chat.rollback(history_len);
+                }
+                return Err(e);
+            }
+        };
+        if mode == Mode::Interleaved {
+            let audio = if chat.keep_reply_audio { &reply_audio[audio_start..] } else { &[] };
+            chat.history.push_assistant(&full_text, audio)?;
+        }
+        match &stats.finish_reason {
+            _ if stats.cancelled => {
+                // The server may or may not have kept the rest of the reply; resend the history.
+                chat.replay = true;
+                println!("\n[interrupted]");
+            }
+            None => println!("[Warning: Server disconnected before completion]"),
+            Some(FinishReason::Length) => {
+                let max_tokens = self.args.max_tokens;
+                println!("\n[truncated at max tokens ({}); see --max-tokens]", max_tokens);
+            }
+            Some(FinishReason::ContentFilter) => {
+                println!("\n[stopped by the server's content filter]");
+            }
+            Some(_) => {}
+        }
+        Ok(stats)
+    }
+
+    fn save_outputs(&mut self, mode: Mode) {
+        if let Some(dir) = self.args.save_audio_dir.as_deref() {
+            if !self.reply_audio.is_empty() {
+                let path = next_audio_path(dir, &mut self.audio_counter, mode);
+                match write_wav_f32(&path, &self.reply_audio, PLAYBACK_SAMPLE_RATE) {
+                    Ok(()) => println!("[audio saved to {}]", path.display()),
+                    Err(e) => print_error(&e, self.client.base_url()),
+                }
+            }
+        }
+        if let (Some(path), Mode::Interleaved) = (self.args.session.as_deref(), mode) {
+            if let Err(e) = self.chat.history.save_changes(path) {
+                print_error(&e, self.client.base_url());
+            }
+        }
+    }
+}
 
 fn print_help() {
     println!(
@@ -22,14 +531,22 @@ fn print_help() {
 Commands:
   /mode <asr|tts|interleaved>  - Switch mode
   /reset                       - Reset context (interleaved mode only)
-  /record                      - Record and transcribe/process audio
-  /wav <path>                  - Load and transcribe/process audio file
+  /record [auto|manual]        - Record and transcribe/process audio
+                                 (auto: stop on silence; manual: press Enter)
+  /voice                       - Hands-free conversation (interleaved; Ctrl-C to stop)
+  /audio <path>                - Load and transcribe/process audio file (alias: /wav)
+  /save <file> [embed]         - Save conversation (audio as side-car WAVs, or embedded)
+                                 (reply audio only with --session or --save-audio-dir)
+  /load <file>                 - Load conversation and replay it to the server
+  /saveaudio <path>            - Save the last response's audio to a WAV file
+  /devices                     - List audio devices (* = in use)
+  /usage                       - Show token usage for this session
   /help                        - Show this help
   /quit or /exit               - Exit the program
 
 Modes:
   ASR (single-shot):
-    - Use /record or /wav to transcribe audio
+    - Use /record or /audio to transcribe audio
     - Each request is independent
 
   TTS (single-shot):
@@ -37,22 +554,110 @@ Modes:
     - Each request is independent
 
   Interleaved (chat):
-    - Type text or use /record or /wav
+    - Type text or use /record or /audio
     - Context is maintained across requests
+      (client-side with --client-history, otherwise on the server)
     - Use /reset to start fresh
+    - Use /save and /load (or --session) to keep conversations
 "#
     );
 }
 
-fn print_stats(stats: &StreamStats) {
+fn playback_config(args: &Args) -> PlaybackConfig {
+    PlaybackConfig {
+        buffer_ms: args.playback_buffer_ms,
+        preroll_ms: args.playback_preroll_ms,
+    }
+}
+
+/// Print the host's devices with their indices, marking `input` and `output` (the devices in
+/// use) with `*`.
+fn print_devices(
+    host: Option<&str>,
+    input: Option<&str>,
+    output: Option<&str>,
+) -> Result<(), Error> {
+    let list = list_devices(host)?;
+    println!("Audio host: {} (available: {})", list.host, available_hosts().join(", "));
+    let section = |title: &str, names: &[String], default: &Option<String>, used: Option<&str>| {
+        println!("{}:", title);
+        if names.is_empty() {
+            println!("  (none)");
+        }
+        for (i, name) in names.iter().enumerate() {
+            let mark = if Some(name.as_str()) == used { "*" } else { " " };
+            let default = if Some(name) == default.as_ref() { " (default)" } else { "" };
+            println!("  {} {:>2}  {}{}", mark, i, name, default);
+        }
+    };
+    section("Input devices", &list.inputs, &list.default_input, input);
+    section("Output devices", &list.outputs, &list.default_output, output);
+    Ok(())
+}
+
+fn mode_label(mode: Mode) -> String {
+    if mode.is_single_shot() {
+        format!("{} (single-shot)", mode)
+    } else {
+        format!("{} (chat)", mode)
+    }
+}
+
+fn print_error(e: &Error, base_url: &str) {
+    println!("Error: {}", e);
+    let hint = match e {
+        Error::Http { status, body }
+            if status.is_client_error() && body.contains("stream_options") =>
+        {
+            "The server does not accept usage requests; run with --no-usage."
+        }
+        Error::Http { status, .. } if status.is_client_error() => {
+            "The server rejected the request; check --max-tokens and the input audio format \
+             (or try --no-usage)."
+        }
+        Error::Http { .. } => "The server failed to handle the request; check its logs.",
+        Error::Transport(_) => {
+            println!("  Could not reach {}; is the server running? (see --base-url)", base_url);
+            return;
+        }
+        Error::Disconnected(_) => "The connection dropped mid-response; the output above is partial.",
+        Error::Server { .. } => "The server failed mid-response; the output above is partial.",
+        Error::Decode(_) => "The server sent data this client cannot decode; is it an LFM2.5-Audio server?",
+        Error::InvalidRequest(_) => return,
+        Error::InvalidAudio(_) => {
+            "Use WAV (any rate or depth), FLAC/MP3/Ogg Vorbis with the compressed-audio feature, \
+             or Opus with the opus feature."
+        }
+        Error::AudioDevice(_) => {
+            "Check the device is connected and free (see --list-devices), or use --no-audio-playback."
+        }
+        Error::Session(_) => "The session file is damaged or from a newer version of this client.",
+        Error::Wav(_) | Error::Io(_) => "Check the file path, its permissions and format.",
+    };
+    println!("  {}", hint);
+}
+
+fn print_stats(stats: &StreamStats, playback: Option<PlaybackStats>, debug: bool) {
     let mut parts = Vec::new();
     if let Some(ttft) = stats.ttft_secs {
         parts.push(format!("ttft {:.3}s", ttft));
     }
-    if stats.text_chunk_count > 1 && stats.text_duration_secs > 0.0 {
+    if let Some(usage) = stats.usage {
+        let audio = usage
+            .completion_audio_tokens
+            .map(|n| format!(" ({} audio)", n))
+            .unwrap_or_default();
+        parts.push(format!(
+            "tokens {} in / {} out{}",
+            usage.prompt_tokens, usage.completion_tokens, audio
+        ));
+        if let Some(rate) = stats.tokens_per_sec() {
+            parts.push(format!("{:.1} tok/s", rate));
+        }
+    } else if stats.text_chunk_count > 1 && stats.text_duration_secs > 0.0 {
         let rate = stats.text_chunk_count as f64 / stats.text_duration_secs;
         parts.push(format!(
-            "text {} tok @ {:.1} tok/s",
+            "text {} chunks @ {:.1} chunks/s",
             stats.text_chunk_count, rate
         ));
     }
@@ -62,6 +667,15 @@ fn print_stats(stats: &StreamStats) {
         parts.push(format!("audio {:.1}s @ {:.0} samples/s", secs, rate));
     }
     parts.push(format!("total {:.3}s", stats.total_secs));
+    if let Some(p) = playback.filter(|_| stats.total_audio_samples > 0) {
+        parts.push(format!("underruns {} | dropped {}", p.underruns, p.dropped_samples));
+    }
+    if stats.cancelled {
+        parts.push("cancelled".to_string());
+    }
+    if debug {
+        parts.push(format!("unparsed events {}", stats.unparsed_events));
+    }
     println!("\n[{}]", parts.join(" | "));
 }
 
@@ -69,66 +683,230 @@ fn print_stats(stats: &StreamStats) {
 #[command(name = "liquid-audio-chat")]
 #[command(about = "Low-latency LFM2.5-Audio chat client")]
 struct Args {
-    #[arg(long, default_value = "http://127.0.0.1:8080/v1")]
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(long, global = true, default_value = DEFAULT_BASE_URL)]
     base_url: String,
-    #[arg(long, default_value = "interleaved")]
-    mode: String,
-    #[arg(long, default_value = "512")]
+    #[arg(long, value_enum, default_value_t = Mode::Interleaved)]
+    mode: Mode,
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_TOKENS)]
     max_tokens: u32,
     #[arg(long)]
     no_audio_playback: bool,
+    /// Keep the conversation client-side and resend it every turn (for stateless servers)
+    #[arg(long)]
+    client_history: bool,
+    /// Session file: loaded at startup if it exists, saved after every chat turn (audio in
+    /// side-car WAVs, only new turns written)
+    #[arg(long)]
+    session: Option<PathBuf>,
+    /// Write each response's audio (24 kHz float WAV) to this directory
+    #[arg(long)]
+    save_audio_dir: Option<PathBuf>,
+    /// Make /record stop automatically when you stop talking (voice activity detection)
+    #[arg(long)]
+    vad: bool,
+    /// Trailing silence that ends a VAD recording
+    #[arg(long, default_value_t = VadConfig::default().trailing_silence_ms)]
+    vad_silence_ms: u32,
+    /// Start in hands-free voice mode (interleaved)
+    #[arg(long)]
+    voice: bool,
+    /// Spoken word that ends voice mode (off by default; Ctrl-C always works). Each utterance
+    /// under 1.5 s then costs an extra transcription, which resets the server context so the
+    /// conversation is resent on the next turn
+    #[arg(long, value_name = "WORD")]
+    voice_stop_word: Option<String>,
+    /// Keep talking over the user in voice mode instead of stopping when they speak
+    #[arg(long)]
+    no_barge_in: bool,
+    /// List audio hosts and devices, then exit
+    #[arg(long)]
+    list_devices: bool,
+    /// Microphone to record from, by name (or part of it) or index from --list-devices
+    #[arg(long)]
+    input_device: Option<String>,
+    /// Speakers to play on, by name (or part of it) or index from --list-devices
+    #[arg(long)]
+    output_device: Option<String>,
+    /// Audio backend to use, e.g. ALSA or JACK (default: the platform default)
+    #[arg(long)]
+    audio_host: Option<String>,
+    /// Playback buffer; when full, reading the response pauses until audio has played
+    #[arg(long, default_value_t = PlaybackConfig::default().buffer_ms)]
+    playback_buffer_ms: u32,
+    /// Audio to buffer before playback starts, smoothing out bursty chunks (adapts upward
+    /// after underruns)
+    #[arg(long, default_value_t = PlaybackConfig::default().preroll_ms)]
+    playback_preroll_ms: u32,
+    /// When a chat reply hits --max-tokens, ask the model to continue, up to N times
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    auto_continue: u32,
+    /// Don't ask for token usage (for servers that reject `stream_options`)
+    #[arg(long, global = true)]
+    no_usage: bool,
+    /// Add stream diagnostics to the stats line (stream events that were not valid JSON)
+    #[arg(long)]
+    debug: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Synthesize prompts to numbered WAV files plus a manifest.json (no REPL)
+    Tts(batch::TtsArgs),
+    /// Transcribe audio files, directories or globs to text, JSONL or CSV (no REPL)
+    Asr(batch::AsrArgs),
 }
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
-    let args = Args::parse();
-    if args.mode != "asr" && args.mode != "tts" && args.mode != "interleaved" {
-        eprintln!("Invalid mode. Use asr, tts, or interleaved.");
-        std::process::exit(1);
-    }
+    let mut args = Args::parse();
 
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(300))
-        .build()
-        .expect("http client");
-    let recorder = AudioRecorder::new();
-    let audio_input_ok = recorder.available();
-    let enable_playback = !args.no_audio_playback;
+    let client = LiquidAudioClient::new(&args.base_url)
+        .expect("http client")
+        .max_tokens(args.max_tokens)
+        .include_usage(!args.no_usage);
+
+    if let Some(command) = args.command.take() {
+        let result = match command {
+            Command::Tts(tts_args) => batch::run_tts(&client, tts_args).await,
+            Command::Asr(asr_args) => batch::run_asr(&client, asr_args).await,
+        };
+        match result {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                print_error(&e, client.base_url());
+                std::process::exit(1);
+            }
+        }
+    }
+    let devices = DeviceSelection {
+        host: args.audio_host.clone(),
+        input: args.input_device.clone(),
+        output: args.output_device.clone(),
+    };
+    if args.list_devices {
+        let (input, output) = (devices.input_device_name().ok(), devices.output_device_name().ok());
+        let host = devices.host.as_deref();
+        if let Err(e) = print_devices(host, input.as_deref(), output.as_deref()) {
+            print_error(&e, client.base_url());
+            std::process::exit(1);
+        }
+        return;
+    }
+    // Anything named explicitly must exist; a missing default only disables that direction.
+    // Each device is resolved once, here, and the banner reuses the names.
+    if args.audio_host.is_some() {
+        if let Err(e) = host_name(devices.host.as_deref()) {
+            print_error(&e, client.base_url());
+            std::process::exit(1);
+        }
+    }
+    let recorder = AudioRecorder::with_devices(&devices);
+    if args.input_device.is_some() && !recorder.available() {
+        // Resolve it again only to report why it failed.
+        if let Err(e) = devices.input_device_name() {
+            print_error(&e, client.base_url());
+            std::process::exit(1);
+        }
+    }
+    let player = if args.no_audio_playback {
+        None
+    } else {
+        match AudioPlayer::open(&devices, playback_config(&args)) {
+            Ok(p) => Some(p),
+            Err(e) if args.output_device.is_some() => {
+                print_error(&e, client.base_url());
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Audio output init failed: {}", e);
+                None
+            }
+        }
+    };
+    let _ = ctrlc::set_handler(|| {
+        if VOICE_ACTIVE.load(Ordering::Relaxed) || RESPONDING.load(Ordering::Relaxed) {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+        } else {
+            std::process::exit(130);
+        }
+    });
 
     println!("==================================================");
     println!("LFM2.5-Audio Interactive Chat (Rust)");
     println!("==================================================");
     println!("Server: {}", args.base_url);
-    println!("Audio output: cpal");
+    let output = match &player {
+        Some(player) => player.device_name(),
+        None if args.no_audio_playback => "off",
+        None => "none",
+    };
+    println!("Audio output: {}", output);
     println!(
         "Audio input:  {}",
-        if audio_input_ok {
-            "microphone"
-        } else {
-            "file only (/wav)"
-        }
+        recorder.device_name().unwrap_or("file only (/audio)")
     );
     println!("Type /help for commands");
     println!("==================================================");
-    println!(
-        "Mode: {}",
-        if args.mode == "asr" || args.mode == "tts" {
-            format!("{} (single-shot)", args.mode)
-        } else {
-            args.mode.clone() + " (chat)"
-        }
-    );
+    println!("Mode: {}", mode_label(args.mode));
 
-    let mut mode = args.mode.clone();
+    let mut mode = args.mode;
     let mut wav_data: Option<Vec<u8>> = None;
-    let mut is_first_message = true;
+    let keep_reply_audio = args.session.is_some() || args.save_audio_dir.is_some();
+    let mut chat = ChatState::new(args.client_history, keep_reply_audio);
+    let vad_config = VadConfig {
+        trailing_silence_ms: args.vad_silence_ms,
+        ..VadConfig::default()
+    };
+    if let Some(dir) = &args.save_audio_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Cannot create {}: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = args.session.as_deref().filter(|p| p.exists()) {
+        match load_session(path, &mut chat) {
+            Ok(()) => {
+                if mode != Mode::Interleaved {
+                    mode = Mode::Interleaved;
+                    println!("Mode: {}", mode_label(mode));
+                }
+            }
+            Err(e) => {
+                print_error(&e, client.base_url());
+                std::process::exit(1);
+            }
+        }
+    }
+    let mut repl = Repl {
+        client,
+        args,
+        recorder,
+        player,
+        devices,
+        vad_config,
+        chat,
+        reply_audio: Vec::new(),
+        audio_counter: 0,
+        usage: SessionUsage::default(),
+    };
+    if repl.args.voice {
+        if mode != Mode::Interleaved {
+            mode = Mode::Interleaved;
+            repl.chat.reset();
+            println!("Mode: {}", mode_label(mode));
+        }
+        repl.voice_loop().await;
+    }
     let mut rl = rustyline::DefaultEditor::new().expect("readline");
 
     loop {
-        let mode_indicator = match mode.as_str() {
-            "asr" => "[ASR]",
-            "tts" => "[TTS]",
-            _ => "[INT]",
+        let mode_indicator = match mode {
+            Mode::Asr => "[ASR]",
+            Mode::Tts => "[TTS]",
+            Mode::Interleaved => "[INT]",
         };
         let audio_indicator = if wav_data.is_some() { " [audio]" } else { "" };
         let prompt = format!("{}{}> ", mode_indicator, audio_indicator);
@@ -153,10 +931,8 @@ async fn main() {
         let user_input = line.trim();
         let mut user_input = user_input;
 
-        if user_input.is_empty() {
-            if mode != "asr" || wav_data.is_none() {
-                continue;
-            }
+        if user_input.is_empty() && (mode != Mode::Asr || wav_data.is_none()) {
+            continue;
         }
 
         if user_input.starts_with('/') {
@@ -174,82 +950,177 @@ async fn main() {
                     continue;
                 }
                 "/mode" => {
-                    if arg == "asr" || arg == "tts" || arg == "interleaved" {
-                        if arg != mode {
-                            mode = arg.to_string();
-                            is_first_message = true;
-                            println!(
-                                "Mode: {}",
-                                if mode == "asr" || mode == "tts" {
-                                    format!("{} (single-shot)", mode)
-                                } else {
-                                    mode.clone() + " (chat)"
-                                }
-                            );
-                        } else {
+                    if arg.is_empty() {
+                        println!("Usage: /mode <asr|tts|interleaved>");
+                        continue;
+                    }
+                    match arg.parse::<Mode>() {
+                        Ok(new_mode) if new_mode == mode => {
                             println!("Already in {} mode", mode);
                         }
-                    } else {
-                        println!("Usage: /mode <asr|tts|interleaved>");
+                        Ok(new_mode) => {
+                            mode = new_mode;
+                            repl.chat.reset();
+                            println!("Mode: {}", mode_label(mode));
+                        }
+                        Err(e) => {
+                            println!("{}", e);
+                            println!("Usage: /mode <asr|tts|interleaved>");
+                        }
                     }
                     continue;
                 }
                 "/reset" => {
-                    if mode != "interleaved" {
+                    if mode != Mode::Interleaved {
                         println!("Reset only available in interleaved mode");
                         continue;
                     }
-                    is_first_message = true;
+                    repl.chat.reset();
                     println!("Context reset");
                     continue;
                 }
+                "/save" => {
+                    let mut save_args = arg.split_whitespace();
+                    let (file, storage) = match (save_args.next(), save_args.next()) {
+                        (Some(f), None) => (f, AudioStorage::Sidecar),
+                        (Some(f), Some("embed")) => (f, AudioStorage::Embedded),
+                        _ => {
+                            println!("Usage: /save <file> [embed]");
+                            continue;
+                        }
+                    };
+                    match repl.chat.history.save(Path::new(file), storage) {
+                        Ok(()) => println!("Saved {} turns to {}", repl.chat.history.len(), file),
+                        Err(e) => print_error(&e, repl.client.base_url()),
+                    }
+                    continue;
+                }
+                "/load" => {
+                    if arg.is_empty() {
+                        println!("Usage: /load <file>");
+                        continue;
+                    }
+                    match load_session(Path::new(arg), &mut repl.chat) {
+                        Ok(()) => {
+                            if mode != Mode::Interleaved {
+                                mode = Mode::Interleaved;
+                                println!("Mode: {}", mode_label(mode));
+                            }
+                        }
+                        Err(e) => print_error(&e, repl.client.base_url()),
+                    }
+                    continue;
+                }
+                "/saveaudio" => {
+                    if arg.is_empty() {
+                        println!("Usage: /saveaudio <path>");
+                        continue;
+                    }
+                    if repl.reply_audio.is_empty() {
+                        println!("No audio in the last response");
+                        continue;
+                    }
+                    match write_wav_f32(Path::new(arg), &repl.reply_audio, PLAYBACK_SAMPLE_RATE) {
+                        Ok(()) => println!(
+                            "Saved {:.1}s of audio to {}",
+                            repl.reply_audio.len() as f64 / PLAYBACK_SAMPLE_RATE as f64,
+                            arg
+                        ),
+                        Err(e) => print_error(&e, repl.client.base_url()),
+                    }
+                    continue;
+                }
+                "/usage" => {
+                    repl.usage.print();
+                    continue;
+                }
+                "/devices" => {
+                    let output = repl.player.as_ref().map(AudioPlayer::device_name);
+                    let host = repl.devices.host.as_deref();
+                    if let Err(e) = print_devices(host, repl.recorder.device_name(), output) {
+                        print_error(&e, repl.client.base_url());
+                    }
+                    continue;
+                }
+                "/voice" => {
+                    if mode != Mode::Interleaved {
+                        mode = Mode::Interleaved;
+                        repl.chat.reset();
+                        println!("Mode: {}", mode_label(mode));
+                    }
+                    repl.voice_loop().await;
+                    continue;
+                }
                 "/record" => {
-                    if mode == "tts" {
+                    if mode == Mode::Tts {
                         println!("Recording not available in TTS mode");
                         continue;
                     }
-                    if !recorder.available() {
-                        println!("[No microphone available. Use /wav to load audio files.]");
+                    if !repl.recorder.available() {
+                        println!("[No microphone available. Use /audio to load audio files.]");
                         continue;
                     }
-                    println!("Recording... (Press Enter to stop)");
-                    let stop_flag = Arc::new(AtomicBool::new(false));
-                    let stop_c = Arc::clone(&stop_flag);
-                    let rec = AudioRecorder::new();
-                    let handle = thread::spawn(move || rec.record_blocking(move || stop_c.load(Ordering::Relaxed)));
-                    // Wait for Enter (already got one line; that was the /record line; need another)
-                    let _ = rl.readline(">> ");
-                    stop_flag.store(true, Ordering::Relaxed);
-                    match handle.join().expect("record thread") {
+                    let auto = match arg {
+                        "" => repl.args.vad,
+                        "auto" => true,
+                        "manual" => false,
+                        _ => {
+                            println!("Usage: /record [auto|manual]");
+                            continue;
+                        }
+                    };
+                    let recorded = if auto {
+                        println!(
+                            "Listening... (stops after {} ms of silence)",
+                            repl.vad_config.trailing_silence_ms
+                        );
+                        tokio::task::block_in_place(|| {
+                            repl.recorder.record_until_silence(repl.vad_config, || false)
+                        })
+                    } else {
+                        println!("Recording... (Press Enter to stop)");
+                        let stop_flag = Arc::new(AtomicBool::new(false));
+                        let stop_c = Arc::clone(&stop_flag);
+                        let rec = repl.recorder.clone();
+                        let handle = thread::spawn(move || rec.record_blocking(move || stop_c.load(Ordering::Relaxed)));
+                        // Wait for Enter (already got one line; that was the /record line; need another)
+                        let _ = rl.readline(">> ");
+                        stop_flag.store(true, Ordering::Relaxed);
+                        handle.join().expect("record thread")
+                    };
+                    match recorded {
                         Ok(bytes) => {
                             if bytes.is_empty() {
+                                if auto {
+                                    println!("No speech detected");
+                                }
                                 continue;
                             }
                             wav_data = Some(bytes);
                             user_input = "";
                         }
                         Err(e) => {
-                            println!("Record error: {}", e);
+                            print_error(&e, repl.client.base_url());
                             continue;
                         }
                     }
                 }
-                "/wav" => {
-                    if mode == "tts" {
+                "/audio" | "/wav" => {
+                    if mode == Mode::Tts {
                         println!("Audio input not available in TTS mode");
                         continue;
                     }
                     if arg.is_empty() {
-                        println!("Usage: /wav <path>");
+                        println!("Usage: {} <path>", cmd);
                         continue;
                     }
-                    match std::fs::read(Path::new(arg)) {
+                    match load_input_audio(Path::new(arg)) {
                         Ok(data) => {
                             wav_data = Some(data);
                             user_input = "";
                         }
                         Err(e) => {
-                            println!("Error loading file: {}", e);
+                            print_error(&e, repl.client.base_url());
                             continue;
                         }
                     }
@@ -267,108 +1138,19 @@ async fn main() {
             Some(user_input.to_string())
         };
 
-        if mode == "asr" {
+        if mode == Mode::Asr {
             if wav_data.is_none() {
-                println!("ASR mode requires audio. Use /record or /wav first.");
-                continue;
-            }
-        } else if mode == "tts" {
-            if text_input.is_none() {
-                println!("TTS mode requires text input.");
+                println!("ASR mode requires audio. Use /record or /audio first.");
                 continue;
             }
+        } else if mode == Mode::Tts && text_input.is_none() {
+            println!("TTS mode requires text input.");
+            continue;
         }
 
-        let (player, playback_handle) = if enable_playback {
-            match AudioPlayer::new() {
-                Ok(p) => {
-                    let handle = p.handle();
-                    (Some(p), Some(handle))
-                }
-                Err(e) => {
-                    eprintln!("Audio output init failed: {}", e);
-                    (None, None)
-                }
-            }
-        } else {
-            (None, None)
-        };
-
-        println!();
-
-        let result = run_request(
-            &client,
-            &args.base_url,
-            &mode,
-            args.max_tokens,
-            text_input.as_deref(),
-            wav_data.as_deref(),
-            &mut is_first_message,
-            playback_handle,
-        )
-        .await;
-
-        if let Some(p) = player {
-            p.stop();
-        }
-
-        match result {
-            Ok(stats) => print_stats(&stats),
-            Err(e) => println!("Error: {}", e),
-        }
+        repl.respond(mode, text_input.as_deref(), wav_data.as_deref(), None)
+            .await;
 
         wav_data = None;
     }
 }
-
-async fn run_request(
-    client: &reqwest::Client,
-    base_url: &str,
-    mode: &str,
-    max_tokens: u32,
-    text_input: Option<&str>,
-    wav_data: Option<&[u8]>,
-    is_first_message: &mut bool,
-    playback_handle: Option<PlaybackHandle>,
-) -> Result<api::StreamStats, String> {
-    let res = if mode == "asr" || mode == "tts" {
-        stream_single_shot(client, base_url, mode, text_input, wav_data, max_tokens).await?
-    } else {
-        let mut messages = Vec::new();
-        if *is_first_message {
-            messages.push(ChatMessage {
-                role: "system".into(),
-                content: MessageContent::Text(SYSTEM_INTERLEAVED.into()).into(),
-            });
-        }
-        if let Some(t) = text_input {
-            messages.push(ChatMessage {
-                role: "user".into(),
-                content: MessageContent::Text(t.to_string()).into(),
-            });
-        }
-        if let Some(wav) = wav_data {
-            messages.push(api::create_audio_message(wav));
-        }
-        let reset = *is_first_message;
-        *is_first_message = false;
-        stream_chat(client, base_url, messages, max_tokens, reset).await?
-    };
-
-    let mut stdout = std::io::stdout();
-    let on_text = |s: &str| {
-        let _ = stdout.write_all(s.as_bytes());
-        let _ = stdout.flush();
-    };
-    let on_audio: Box<dyn FnMut(&[f32]) + Send> = if let Some(h) = playback_handle {
-        Box::new(move |samples: &[f32]| h.add_samples(samples))
-    } else {
-        Box::new(|_| {})
-    };
-
-    let (_, stats) = process_stream(res, on_text, on_audio).await?;
-    if !stats.completed {
-        println!("[Warning: Server disconnected before completion]");
-    }
-    Ok(stats)
-}
@@ -0,0 +1,212 @@
+//! Fixed-capacity sample ring for the playback path, split into a shared producer side
+//! (`SampleRing`) and a single consumer (`RingReader`).
+//!
+//! The reader (owned by the audio callback) never locks or allocates. Pushes are serialized by
+//! a mutex so cloned `PlaybackHandle`s stay sound; it is uncontended in practice.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+struct Slots {
+    buf: Box<[UnsafeCell<f32>]>,
+    /// Total samples ever written / read; positions are these modulo the capacity.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    producer: Mutex<()>,
+}
+
+// Writers are serialized by `producer`, and only the one `RingReader` (through `&mut self`)
+// reads; the two touch disjoint slots, ordered by the head/tail atomics.
+unsafe impl Sync for Slots {}
+
+impl Slots {
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        head.wrapping_sub(tail)
+    }
+}
+
+/// Producer side of the ring; can be shared between threads.
+pub(crate) struct SampleRing {
+    slots: Arc<Slots>,
+}
+
+/// Consumer side of the ring. There is exactly one per ring and it is not `Clone`, so reads
+/// can never race each other.
+pub(crate) struct RingReader {
+    slots: Arc<Slots>,
+}
+
+impl SampleRing {
+    /// A ring holding up to `capacity` samples (at least one), and its only reader.
+    pub(crate) fn new(capacity: usize) -> (Self, RingReader) {
+        let slots = Arc::new(Slots {
+            buf: (0..capacity.max(1)).map(|_| UnsafeCell::new(0.0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            producer: Mutex::new(()),
+        });
+        let reader = RingReader {
+            slots: Arc::clone(&slots),
+        };
+        (Self { slots }, reader)
+    }
+
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Copy as many of `samples` as fit; returns how many were written.
+    pub(crate) fn push(&self, samples: &[f32]) -> usize {
+        let slots = &*self.slots;
+        let _guard = slots.producer.lock().unwrap_or_else(|e| e.into_inner());
+        let head = slots.head.load(Ordering::Relaxed);
+        let tail = slots.tail.load(Ordering::Acquire);
+        let free = self.capacity() - head.wrapping_sub(tail);
+        let n = free.min(samples.len());
+        for (i, &sample) in samples[..n].iter().enumerate() {
+            let slot = &slots.buf[head.wrapping_add(i) % slots.capacity()];
+            // SAFETY: slots between head and tail + capacity belong to the producer until
+            // `head` is published below.
+            unsafe { *slot.get() = sample };
+        }
+        slots.head.store(head.wrapping_add(n), Ordering::Release);
+        n
+    }
+}
+
+impl RingReader {
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Move up to `out.len()` samples into `out`; returns how many were read.
+    pub(crate) fn pop(&mut self, out: &mut [f32]) -> usize {
+        let slots = &*self.slots;
+        let tail = slots.tail.load(Ordering::Relaxed);
+        let head = slots.head.load(Ordering::Acquire);
+        let n = head.wrapping_sub(tail).min(out.len());
+        for (i, out) in out[..n].iter_mut().enumerate() {
+            let slot = &slots.buf[tail.wrapping_add(i) % slots.capacity()];
+            // SAFETY: slots between tail and head were published by the producer and are not
+            // rewritten until `tail` moves past them; this is the only reader.
+            *out = unsafe { *slot.get() };
+        }
+        slots.tail.store(tail.wrapping_add(n), Ordering::Release);
+        n
+    }
+
+    /// Discard everything queued.
+    pub(crate) fn clear(&mut self) {
+        let head = self.slots.head.load(Ordering::Acquire);
+        self.slots.tail.store(head, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn push_pop_len_clear() {
+        let (ring, mut reader) = SampleRing::new(8);
+        assert_eq!(ring.capacity(), 8);
+        assert_eq!(ring.push(&[1.0, 2.0, 3.0]), 3);
+        assert_eq!(ring.len(), 3);
+        let mut out = [0.0; 2];
+        assert_eq!(reader.pop(&mut out), 2);
+        assert_eq!(out, [1.0, 2.0]);
+        assert_eq!(ring.len(), 1);
+        ring.push(&[4.0, 5.0]);
+        reader.clear();
+        assert_eq!(ring.len(), 0);
+        assert_eq!(reader.pop(&mut out), 0);
+    }
+
+    #[test]
+    fn partial_push_when_full() {
+        let (ring, mut reader) = SampleRing::new(4);
+        assert_eq!(ring.push(&[1.0, 2.0, 3.0]), 3);
+        assert_eq!(ring.push(&[4.0, 5.0, 6.0]), 1);
+        assert_eq!(ring.push(&[7.0]), 0);
+        assert_eq!(ring.len(), 4);
+        let mut out = [0.0; 8];
+        assert_eq!(reader.pop(&mut out), 4);
+        assert_eq!(out[..4], [1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn wraps_around_the_buffer() {
+        let (ring, mut reader) = SampleRing::new(5);
+        let mut out = [0.0; 3];
+        let mut next = 0.0;
+        let mut expected = 0.0;
+        // Positions run many times round the buffer, in steps that do not divide it.
+        for _ in 0..100 {
+            let chunk = [next, next + 1.0, next + 2.0];
+            assert_eq!(ring.push(&chunk), 3);
+            next += 3.0;
+            assert_eq!(reader.pop(&mut out), 3);
+            for &sample in &out {
+                assert_eq!(sample, expected);
+                expected += 1.0;
+            }
+        }
+        assert_eq!(ring.len(), 0);
+    }
+
+    #[test]
+    fn zero_capacity_holds_one_sample() {
+        let (ring, _reader) = SampleRing::new(0);
+        assert_eq!(ring.push(&[1.0, 2.0]), 1);
+    }
+
+    #[test]
+    fn concurrent_producer_and_consumer_keep_order() {
+        const TOTAL: usize = 1_000_000;
+        let (ring, mut reader) = SampleRing::new(1021);
+        let producer = std::thread::spawn(move || {
+            let samples: Vec<f32> = (0..TOTAL).map(|i| i as f32).collect();
+            let mut sent = 0;
+            for block in [1, 7, 64, 300].iter().cycle() {
+                if sent == TOTAL {
+                    break;
+                }
+                let end = (sent + block).min(TOTAL);
+                sent += ring.push(&samples[sent..end]);
+                if sent < end {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut out = [0.0; 97];
+        let mut received = 0;
+        while received < TOTAL {
+            let n = reader.pop(&mut out);
+            for &sample in &out[..n] {
+                assert_eq!(sample, received as f32, "sample {} out of order", received);
+                received += 1;
+            }
+            if n == 0 {
+                std::thread::yield_now();
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(reader.len(), 0);
+    }
+}
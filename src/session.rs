@@ -0,0 +1,355 @@
+//! Session files: a conversation saved as JSON that can be replayed to restore server context.
+//!
+//! Format (version 1):
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "turns": [
+//!     { "role": "system", "text": "Respond with interleaved text and audio." },
+//!     { "role": "user", "audio": { "data": "<base64 WAV bytes>" } },
+//!     { "role": "assistant", "text": "Hello!", "audio": { "file": "chat.audio/002-assistant.wav" } }
+//!   ]
+//! }
+//! ```
+//!
+//! `text` and `audio` are optional. `audio` is either embedded (`data`) or a side-car WAV file
+//! (`file`, relative to the JSON file's directory). User audio is the input exactly as sent;
+//! assistant audio is the streamed 24 kHz output, stored for review and never sent back.
+//! Sending the loaded turns with `reset_context: true` rebuilds the model's context.
+//!
+//! Session files may come from someone else, so loading never reads outside the JSON file's
+//! directory (no absolute or `..` side-car paths, symlinks included) and user audio goes
+//! through the same `normalize_wav` checks as `/audio` before it can be sent.
+
+use crate::api::B64;
+use crate::audio::normalize_wav;
+use crate::conversation::{Conversation, Role, Turn};
+use crate::error::{Error, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+pub const SESSION_VERSION: u32 = 1;
+
+/// Where `save` puts turn audio.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioStorage {
+    /// Base64 WAV inside the JSON file (single self-contained file).
+    Embedded,
+    /// WAV files in a `<name>.audio/` directory next to the JSON file.
+    Sidecar,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    version: u32,
+    turns: Vec<StoredTurn>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTurn {
+    role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audio: Option<StoredAudio>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredAudio {
+    Embedded { data: String },
+    File { file: String },
+}
+
+/// Resolve a side-car `file` entry, refusing anything that would leave `base_dir`.
+fn resolve_sidecar(base_dir: &Path, file: &str) -> Result<PathBuf> {
+    let rel = Path::new(file);
+    let plain = rel
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if file.is_empty() || !plain {
+        return Err(Error::Session(format!(
+            "audio file '{}' must be a relative path inside the session directory",
+            file
+        )));
+    }
+    let base = if base_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base_dir
+    };
+    let full = base.join(rel);
+    let canonical = |p: &Path| {
+        p.canonicalize()
+            .map_err(|e| Error::Session(format!("{}: {}", p.display(), e)))
+    };
+    let (base, resolved) = (canonical(base)?, canonical(&full)?);
+    if !resolved.starts_with(&base) {
+        return Err(Error::Session(format!(
+            "audio file '{}' resolves outside the session directory",
+            file
+        )));
+    }
+    Ok(resolved)
+}
+
+fn sidecar_dir_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "session".into());
+    format!("{}.audio", stem)
+}
+
+impl Conversation {
+    /// Write the conversation to `path` in the session format described in the module docs.
+    pub fn save(&self, path: &Path, storage: AudioStorage) -> Result<()> {
+        self.write(path, storage, 0)
+    }
+
+    /// Save to `path` again with side-car audio, writing only the WAVs of turns added since the
+    /// last `save_changes` (see `unsaved_from`); the JSON itself only holds text and file names.
+    /// For saving after every turn, always to the same `path`.
+    pub fn save_changes(&mut self, path: &Path) -> Result<()> {
+        self.write(path, AudioStorage::Sidecar, self.unsaved_from())?;
+        self.mark_saved();
+        Ok(())
+    }
+
+    /// Side-car WAVs of turns before `unchanged` are assumed to be on disk from an earlier save.
+    fn write(&self, path: &Path, storage: AudioStorage, unchanged: usize) -> Result<()> {
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let sidecar_dir = sidecar_dir_name(path);
+        let mut turns = Vec::with_capacity(self.len());
+        for (i, turn) in self.turns().iter().enumerate() {
+            let audio = match (&turn.audio, storage) {
+                (None, _) => None,
+                (Some(wav), AudioStorage::Embedded) => Some(StoredAudio::Embedded {
+                    data: B64.encode(wav),
+                }),
+                (Some(wav), AudioStorage::Sidecar) => {
+                    let file = format!("{}/{:03}-{}.wav", sidecar_dir, i, turn.role.as_str());
+                    if i >= unchanged {
+                        let full = base_dir.join(&file);
+                        if let Some(dir) = full.parent() {
+                            std::fs::create_dir_all(dir)?;
+                        }
+                        std::fs::write(&full, wav)?;
+                    }
+                    Some(StoredAudio::File { file })
+                }
+            };
+            turns.push(StoredTurn {
+                role: turn.role,
+                text: turn.text.clone(),
+                audio,
+            });
+        }
+        let file = SessionFile {
+            version: SESSION_VERSION,
+            turns,
+        };
+        let json = serde_json::to_vec_pretty(&file).map_err(|e| Error::Session(e.to_string()))?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Read a session file written by `save` (either audio storage).
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let file: SessionFile =
+            serde_json::from_slice(&bytes).map_err(|e| Error::Session(e.to_string()))?;
+        if file.version > SESSION_VERSION {
+            return Err(Error::Session(format!(
+                "unsupported version {} (max {})",
+                file.version, SESSION_VERSION
+            )));
+        }
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut turns = Vec::with_capacity(file.turns.len());
+        for (i, stored) in file.turns.into_iter().enumerate() {
+            let audio = match stored.audio {
+                None => None,
+                Some(StoredAudio::Embedded { data }) => Some(
+                    B64.decode(data.as_bytes())
+                        .map_err(|e| Error::Session(format!("embedded audio: {}", e)))?,
+                ),
+                Some(StoredAudio::File { file }) => {
+                    let full = resolve_sidecar(base_dir, &file)?;
+                    Some(
+                        std::fs::read(&full)
+                            .map_err(|e| Error::Session(format!("{}: {}", full.display(), e)))?,
+                    )
+                }
+            };
+            // User audio is sent on replay; hold it to the same rules as /audio.
+            let audio = match audio {
+                Some(wav) if stored.role == Role::User => Some(
+                    normalize_wav(&wav)
+                        .map_err(|e| Error::Session(format!("turn {}: {}", i, e)))?,
+                ),
+                audio => audio,
+            };
+            turns.push(Turn {
+                role: stored.role,
+                text: stored.text,
+                audio,
+            });
+        }
+        Ok(Conversation::from_turns(turns))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::samples_to_wav_bytes;
+
+    /// Fresh empty directory under the system temp dir, unique per test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lac-session-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sample_conversation() -> Conversation {
+        let tone: Vec<f32> = (0..1600).map(|i| (i as f32 * 0.1).sin() * 0.5).collect();
+        let user_wav = samples_to_wav_bytes(&tone, 16_000).unwrap();
+        let mut conv = Conversation::new();
+        conv.push_user(Some("hello"), Some(&user_wav));
+        conv.push_assistant("Hi there", &tone).unwrap();
+        conv
+    }
+
+    fn round_trip(storage: AudioStorage, name: &str) {
+        let dir = temp_dir(name);
+        let path = dir.join("chat.json");
+        let conv = sample_conversation();
+        conv.save(&path, storage).unwrap();
+        let loaded = Conversation::load(&path).unwrap();
+        assert_eq!(loaded.len(), conv.len());
+        for (a, b) in conv.turns().iter().zip(loaded.turns()) {
+            assert_eq!(a.role, b.role);
+            assert_eq!(a.text, b.text);
+            assert_eq!(a.audio, b.audio);
+        }
+        assert_eq!(
+            dir.join("chat.audio").exists(),
+            storage == AudioStorage::Sidecar
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn round_trip_embedded() {
+        round_trip(AudioStorage::Embedded, "embedded");
+    }
+
+    #[test]
+    fn round_trip_sidecar() {
+        round_trip(AudioStorage::Sidecar, "sidecar");
+    }
+
+    #[test]
+    fn save_changes_writes_only_new_audio() {
+        let dir = temp_dir("changes");
+        let path = dir.join("chat.json");
+        let wav = |path: &str| dir.join("chat.audio").join(path);
+        let mut conv = sample_conversation();
+        conv.save_changes(&path).unwrap();
+        assert!(wav("001-user.wav").exists() && wav("002-assistant.wav").exists());
+
+        // Files of turns saved before are left alone, so a marker written over one survives.
+        std::fs::write(wav("001-user.wav"), b"marker").unwrap();
+        let user_wav = conv.turns()[1].audio.clone().unwrap();
+        conv.push_user(Some("again"), Some(&user_wav));
+        conv.save_changes(&path).unwrap();
+        assert_eq!(std::fs::read(wav("001-user.wav")).unwrap(), b"marker");
+        assert_eq!(std::fs::read(wav("003-user.wav")).unwrap(), user_wav);
+
+        // Turns replaced after a rollback are written again.
+        conv.truncate(1);
+        conv.push_user(Some("replaced"), Some(&user_wav));
+        conv.save_changes(&path).unwrap();
+        assert_eq!(std::fs::read(wav("001-user.wav")).unwrap(), user_wav);
+        let loaded = Conversation::load(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.turns()[1].text.as_deref(), Some("replaced"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn load_with_file(dir: &Path, file: &str) -> Result<Conversation> {
+        let json = serde_json::json!({
+            "version": 1,
+            "turns": [{ "role": "user", "audio": { "file": file } }]
+        });
+        let path = dir.join("shared.json");
+        std::fs::write(&path, json.to_string()).unwrap();
+        Conversation::load(&path)
+    }
+
+    #[test]
+    fn sidecar_paths_cannot_leave_the_session_directory() {
+        let root = temp_dir("traversal");
+        let dir = root.join("sessions");
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret = root.join("secret.wav");
+        std::fs::write(&secret, samples_to_wav_bytes(&[0.0; 16], 16_000).unwrap()).unwrap();
+
+        let absolute = secret.to_string_lossy().into_owned();
+        for file in ["../secret.wav", "x/../../secret.wav", absolute.as_str(), ""] {
+            assert!(
+                matches!(load_with_file(&dir, file), Err(Error::Session(_))),
+                "{:?} was accepted",
+                file
+            );
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&secret, dir.join("link.wav")).unwrap();
+            assert!(matches!(
+                load_with_file(&dir, "link.wav"),
+                Err(Error::Session(_))
+            ));
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn loaded_user_audio_is_normalized() {
+        let dir = temp_dir("normalize");
+        let stereo_48k = {
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            let spec = hound::WavSpec {
+                channels: 2,
+                sample_rate: 48_000,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            let mut writer = hound::WavWriter::new(&mut cursor, spec).unwrap();
+            for i in 0..4800 {
+                writer.write_sample((i % 100) as i16).unwrap();
+            }
+            writer.finalize().unwrap();
+            cursor.into_inner()
+        };
+        std::fs::write(dir.join("in.wav"), stereo_48k).unwrap();
+        let loaded = load_with_file(&dir, "in.wav").unwrap();
+        let wav = loaded.turns()[0].audio.as_deref().unwrap();
+        let spec = hound::WavReader::new(wav).unwrap().spec();
+        assert_eq!(
+            (spec.channels, spec.sample_rate, spec.bits_per_sample),
+            (1, 16_000, 16)
+        );
+
+        std::fs::write(dir.join("bad.wav"), b"not audio").unwrap();
+        assert!(matches!(
+            load_with_file(&dir, "bad.wav"),
+            Err(Error::Session(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
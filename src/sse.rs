@@ -0,0 +1,311 @@
+//! Incremental decoder for the response stream: Server-Sent Events as specified by WHATWG
+//! (`text/event-stream`), plus bare NDJSON lines for servers that skip the SSE framing.
+//!
+//! Bytes are buffered raw in a `BytesMut` and only split off once a whole line is available, so
+//! UTF-8 sequences and CRLF pairs split across network chunks are handled. Lines end with LF,
+//! CRLF or CR. `data:` lines accumulate until a blank line dispatches the event; `event:`, `id:`
+//! and `retry:` are tracked; `:` comments and unknown fields are ignored. A line starting with
+//! `{` is taken as one NDJSON record and dispatched on its own.
+//!
+//! Single-line payloads (the common case) are handed out as slices of the receive buffer
+//! without copying or UTF-8 decoding; see `SseEvent::text`.
+
+use bytes::{Buf, Bytes, BytesMut};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+/// One dispatched event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SseEvent {
+    /// `event:` field; `None` for the default `message` type (and for NDJSON records).
+    pub event: Option<String>,
+    /// `data:` lines joined with `\n`, as received.
+    pub data: Bytes,
+    /// Last event ID seen so far in the stream, if any.
+    pub id: Option<String>,
+}
+
+impl SseEvent {
+    /// Default (`message`) event, the only type the chat completion stream uses.
+    pub fn is_message(&self) -> bool {
+        self.event.is_none()
+    }
+
+    /// `data` as text; borrowed unless it holds invalid UTF-8, which is replaced.
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.data)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buf: BytesMut,
+    /// Leading bytes of `buf` already searched for a line end.
+    scanned: usize,
+    /// The previous line ended in CR; an LF at the start of the next chunk belongs to it.
+    skip_lf: bool,
+    started: bool,
+    data: Option<Bytes>,
+    event: Option<String>,
+    last_id: Option<String>,
+    retry_ms: Option<u64>,
+    ready: VecDeque<SseEvent>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append raw bytes from the network.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Next complete event, if the buffered bytes contain one.
+    pub fn next_event(&mut self) -> Option<SseEvent> {
+        while self.ready.is_empty() {
+            let line = self.next_line()?;
+            self.process_line(line);
+        }
+        self.ready.pop_front()
+    }
+
+    /// End of stream: a trailing NDJSON record without a newline is still returned; a partly
+    /// received SSE event is discarded, as the spec requires.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if let Some(event) = self.next_event() {
+            return Some(event);
+        }
+        let rest = self.buf.split().freeze();
+        self.scanned = 0;
+        self.data = None;
+        self.event = None;
+        let record = trim_ascii(rest);
+        record.starts_with(b"{").then(|| SseEvent {
+            event: None,
+            data: record,
+            id: self.last_id.clone(),
+        })
+    }
+
+    /// Reconnection time from the last valid `retry:` field.
+    pub fn retry_ms(&self) -> Option<u64> {
+        self.retry_ms
+    }
+
+    fn next_line(&mut self) -> Option<Bytes> {
+        if self.skip_lf && !self.buf.is_empty() {
+            if self.buf[0] == b'\n' {
+                self.buf.advance(1);
+            }
+            self.skip_lf = false;
+        }
+        let end = match self.buf[self.scanned..].iter().position(|&b| b == b'\n' || b == b'\r') {
+            Some(i) => self.scanned + i,
+            None => {
+                self.scanned = self.buf.len();
+                return None;
+            }
+        };
+        let mut line = self.buf.split_to(end).freeze();
+        self.skip_lf = self.buf[0] == b'\r';
+        self.buf.advance(1);
+        self.scanned = 0;
+        if !self.started {
+            if line.starts_with("\u{feff}".as_bytes()) {
+                line.advance(3);
+            }
+            self.started = true;
+        }
+        Some(line)
+    }
+
+    fn process_line(&mut self, line: Bytes) {
+        if line.is_empty() {
+            self.dispatch();
+            return;
+        }
+        if line[0] == b':' {
+            return;
+        }
+        if self.data.is_none() && line.trim_ascii_start().starts_with(b"{") {
+            self.ready.push_back(SseEvent {
+                event: None,
+                data: trim_ascii(line),
+                id: self.last_id.clone(),
+            });
+            return;
+        }
+        let (field, value) = match line.iter().position(|&b| b == b':') {
+            Some(colon) => {
+                let start = colon + 1 + usize::from(line.get(colon + 1) == Some(&b' '));
+                (&line[..colon], line.slice(start..))
+            }
+            None => (&line[..], Bytes::new()),
+        };
+        match field {
+            b"data" => {
+                self.data = Some(match self.data.take() {
+                    None => value,
+                    Some(prev) => {
+                        let mut joined = BytesMut::with_capacity(prev.len() + 1 + value.len());
+                        joined.extend_from_slice(&prev);
+                        joined.extend_from_slice(b"\n");
+                        joined.extend_from_slice(&value);
+                        joined.freeze()
+                    }
+                });
+            }
+            b"event" => self.event = Some(String::from_utf8_lossy(&value).into_owned()),
+            b"id" if !value.contains(&0) => {
+                self.last_id = Some(String::from_utf8_lossy(&value).into_owned());
+            }
+            b"retry" if !value.is_empty() && value.iter().all(u8::is_ascii_digit) => {
+                self.retry_ms = std::str::from_utf8(&value).ok().and_then(|v| v.parse().ok());
+            }
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self) {
+        let event = self.event.take();
+        let Some(data) = self.data.take() else {
+            return;
+        };
+        self.ready.push_back(SseEvent {
+            event: event.filter(|e| !e.is_empty() && e != "message"),
+            data,
+            id: self.last_id.clone(),
+        });
+    }
+}
+
+/// Zero-copy `trim_ascii` for `Bytes`.
+fn trim_ascii(bytes: Bytes) -> Bytes {
+    let start = bytes.len() - bytes.trim_ascii_start().len();
+    let end = bytes.trim_ascii_end().len();
+    if start >= end {
+        Bytes::new()
+    } else {
+        bytes.slice(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_chunks(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            decoder.push(chunk);
+            while let Some(event) = decoder.next_event() {
+                events.push(event);
+            }
+        }
+        events.extend(decoder.finish());
+        events
+    }
+
+    /// Decode `input` split at every possible position (and byte by byte); all must agree.
+    fn decode_fragmented(input: &[u8]) -> Vec<SseEvent> {
+        let whole = decode_chunks(&[input]);
+        for split in 0..=input.len() {
+            let (a, b) = input.split_at(split);
+            assert_eq!(decode_chunks(&[a, b]), whole, "split at {}", split);
+        }
+        let bytes: Vec<&[u8]> = input.chunks(1).collect();
+        assert_eq!(decode_chunks(&bytes), whole, "byte by byte");
+        whole
+    }
+
+    fn message(data: &str) -> SseEvent {
+        SseEvent {
+            event: None,
+            data: Bytes::copy_from_slice(data.as_bytes()),
+            id: None,
+        }
+    }
+
+    #[test]
+    fn data_with_and_without_space() {
+        let events = decode_fragmented(b"data: {\"a\":1}\n\ndata:{\"b\":2}\n\n");
+        assert_eq!(events, vec![message("{\"a\":1}"), message("{\"b\":2}")]);
+    }
+
+    #[test]
+    fn multi_line_data_is_joined() {
+        let events = decode_fragmented(b"data: first\ndata:second\ndata\n\n");
+        assert_eq!(events, vec![message("first\nsecond\n")]);
+    }
+
+    #[test]
+    fn line_endings_lf_crlf_cr() {
+        let events = decode_fragmented(b"data: a\r\n\r\ndata: b\r\rdata: c\n\n");
+        assert_eq!(events, vec![message("a"), message("b"), message("c")]);
+    }
+
+    #[test]
+    fn utf8_split_across_chunks() {
+        let input = "data: héllo ♪ 🎵\n\n".as_bytes();
+        assert_eq!(decode_fragmented(input), vec![message("héllo ♪ 🎵")]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced_not_dropped() {
+        let events = decode_fragmented(b"data: a\xffb\n\ndata: next\n\n");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].text(), "a\u{fffd}b");
+        assert_eq!(events[1], message("next"));
+    }
+
+    #[test]
+    fn comments_and_unknown_fields_are_ignored() {
+        let events = decode_fragmented(b": keep-alive\nfoo: bar\ndata: x\n\n:\n\n");
+        assert_eq!(events, vec![message("x")]);
+    }
+
+    #[test]
+    fn event_id_and_retry_fields() {
+        let mut decoder = SseDecoder::new();
+        decoder.push(b"retry: 1500\nid: 7\nevent: error\ndata: oops\n\ndata: ok\n\n");
+        let first = decoder.next_event().unwrap();
+        assert_eq!(first.event.as_deref(), Some("error"));
+        assert_eq!(first.text(), "oops");
+        assert_eq!(first.id.as_deref(), Some("7"));
+        let second = decoder.next_event().unwrap();
+        assert!(second.is_message());
+        assert_eq!(second.id.as_deref(), Some("7"));
+        assert_eq!(decoder.retry_ms(), Some(1500));
+
+        decoder.push(b"retry: soon\n\n");
+        assert_eq!(decoder.next_event(), None);
+        assert_eq!(decoder.retry_ms(), Some(1500));
+    }
+
+    #[test]
+    fn blank_event_without_data_is_not_dispatched() {
+        assert_eq!(decode_fragmented(b"event: ping\n\n\n\ndata: x\n\n"), vec![message("x")]);
+    }
+
+    #[test]
+    fn leading_bom_is_skipped() {
+        assert_eq!(decode_fragmented("\u{feff}data: x\n\n".as_bytes()), vec![message("x")]);
+    }
+
+    #[test]
+    fn ndjson_records() {
+        let events = decode_fragmented(b"{\"a\":1}\r\n  {\"b\":2}\n{\"c\":3}");
+        assert_eq!(
+            events,
+            vec![message("{\"a\":1}"), message("{\"b\":2}"), message("{\"c\":3}")]
+        );
+    }
+
+    #[test]
+    fn unterminated_sse_event_is_discarded_at_end() {
+        assert_eq!(decode_fragmented(b"data: a\n\ndata: partial\n"), vec![message("a")]);
+    }
+}